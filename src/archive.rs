@@ -0,0 +1,931 @@
+//! Archives of builds to share them between machines
+//!
+//! `llvmmgmt archive` packs a build into a tar archive compressed by multithreaded xz or zstd,
+//! and `llvmmgmt import` (or `expand`) unpacks it into the data directory of another machine:
+//!
+//! ```shell
+//! llvmmgmt archive 18.1.8 --output /shared/llvm-18.1.8.tar.zst
+//! llvmmgmt import /shared/llvm-18.1.8.tar.zst --as llvm-18  # --force overwrites an existing build
+//! ```
+//!
+//! The compression is given by `--compression`, or guessed from the extension of the output, and `xz` by default.
+//! The archive contains the build as `{name}/...` and a [Manifest] as `llvmmgmt-manifest.toml` at the end,
+//! which records the build name, version, entry setting, and SHA-256 checksums of the files.
+//!
+//! Import rejects absolute paths, path traversal, and links pointing outside of the build,
+//! and verifies the files by the manifest, and the archive by `{archive}.sha256` if exists.
+
+use log::{info, warn};
+use semver::Version;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Read, Write},
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    build::{Build, BuildInfo},
+    config::data_dir,
+    entry::EntrySetting,
+    error::*,
+    relocate::{self, Relocation},
+};
+
+/// File name of the manifest in archives
+pub const MANIFEST_FN: &str = "llvmmgmt-manifest.toml";
+
+/// Compression of archives
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Xz,
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+    fn from_str(compression: &str) -> Result<Self> {
+        match compression {
+            "xz" => Ok(Compression::Xz),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(Error::UnsupportedCompression {
+                compression: compression.into(),
+            }),
+        }
+    }
+}
+
+impl Compression {
+    /// Guess from the extension of an archive
+    ///
+    /// ```
+    /// use llvmmgmt::archive::Compression;
+    /// use std::path::Path;
+    /// assert_eq!(Compression::from_path(Path::new("llvm.tar.zst")), Some(Compression::Zstd));
+    /// assert_eq!(Compression::from_path(Path::new("llvm.tar")), None);
+    /// ```
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::Xz => "tar.xz",
+            Compression::Zstd => "tar.zst",
+        }
+    }
+}
+
+/// Description of an archived build
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
+pub struct Manifest {
+    /// Name of the build
+    pub name: String,
+
+    /// Version of the entry if known
+    pub version: Option<String>,
+
+    /// Absolute prefix of the build where it was archived
+    pub prefix: PathBuf,
+
+    /// Recorded information of the build
+    pub info: Option<BuildInfo>,
+
+    /// Setting of the entry as built, recorded in the fingerprint
+    pub setting: Option<EntrySetting>,
+
+    /// SHA-256 checksums of the regular files, relative to the prefix
+    #[serde(default)]
+    pub files: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    fn new(build: &Build) -> Result<Self> {
+        let info = build.info()?;
+        Ok(Manifest {
+            name: build.name().into(),
+            version: info
+                .as_ref()
+                .and_then(|info| Version::parse(&info.entry).ok())
+                .map(|version| version.to_string()),
+            prefix: build.prefix().into(),
+            setting: info
+                .as_ref()
+                .and_then(|info| info.fingerprint.as_ref())
+                .map(|fingerprint| fingerprint.setting.clone()),
+            info,
+            files: BTreeMap::new(),
+        })
+    }
+
+    fn to_toml(&self) -> Result<String> {
+        // Serialize through Value to put tables after plain values
+        Ok(toml::to_string(&toml::Value::try_from(self)?)?)
+    }
+}
+
+/// Compressed output
+enum Encoder {
+    Xz(xz2::write::XzEncoder<fs::File>),
+    Zstd(zstd::Encoder<'static, fs::File>),
+}
+
+impl Encoder {
+    fn new(file: fs::File, compression: Compression) -> io::Result<Self> {
+        let threads = num_cpus::get() as u32;
+        Ok(match compression {
+            Compression::Xz => {
+                let stream = xz2::stream::MtStreamBuilder::new()
+                    .threads(threads)
+                    .preset(6)
+                    .check(xz2::stream::Check::Crc64)
+                    .encoder()?;
+                Encoder::Xz(xz2::write::XzEncoder::new_stream(file, stream))
+            }
+            Compression::Zstd => {
+                let mut encoder = zstd::Encoder::new(file, 0)?;
+                encoder.multithread(threads)?;
+                Encoder::Zstd(encoder)
+            }
+        })
+    }
+
+    fn finish(self) -> io::Result<fs::File> {
+        match self {
+            Encoder::Xz(encoder) => encoder.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Xz(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Xz(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Reader computing SHA-256 of the read bytes
+pub(crate) struct HashReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R> HashReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        HashReader {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// SHA-256 of the bytes read so far in hex
+    pub(crate) fn checksum(self) -> String {
+        format!("{:x}", self.hasher.finalize())
+    }
+}
+
+impl<R: Read> Read for HashReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// SHA-256 of a file in hex
+pub fn file_checksum(path: &Path) -> Result<String> {
+    let mut reader = HashReader::new(fs::File::open(path).with(path)?);
+    io::copy(&mut reader, &mut io::sink()).with(path)?;
+    Ok(reader.checksum())
+}
+
+/// Archive a build into `output`
+pub fn create(
+    build: &Build,
+    output: &Path,
+    compression: Compression,
+    verbose: bool,
+) -> Result<Manifest> {
+    let mut manifest = Manifest::new(build)?;
+    let file = fs::File::create(output).with(output)?;
+    let mut builder = tar::Builder::new(Encoder::new(file, compression).with(output)?);
+    let root = PathBuf::from(build.name());
+    builder
+        .append_dir(&root, build.prefix())
+        .with(build.prefix())?;
+    append_dir(
+        &mut builder,
+        build.prefix(),
+        build.prefix(),
+        &root,
+        &mut manifest.files,
+        verbose,
+    )?;
+
+    let bytes = manifest.to_toml()?.into_bytes();
+    let mut header = tar::Header::new_gnu();
+    header.set_size(bytes.len() as u64);
+    header.set_mode(0o644);
+    builder
+        .append_data(&mut header, MANIFEST_FN, bytes.as_slice())
+        .with(output)?;
+    builder
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .with(output)?;
+    info!(
+        "Archived {} files of {} into {}",
+        manifest.files.len(),
+        build.name(),
+        output.display()
+    );
+    Ok(manifest)
+}
+
+fn append_dir(
+    builder: &mut tar::Builder<Encoder>,
+    prefix: &Path,
+    dir: &Path,
+    root: &Path,
+    files: &mut BTreeMap<String, String>,
+    verbose: bool,
+) -> Result<()> {
+    let mut paths = fs::read_dir(dir)
+        .with(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()
+        .with(dir)?;
+    paths.sort();
+    for path in paths {
+        let rel = path.strip_prefix(prefix).expect("file in prefix");
+        let name = root.join(rel);
+        if verbose {
+            println!("{}", name.display());
+        }
+        let meta = fs::symlink_metadata(&path).with(&path)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_metadata(&meta);
+        if meta.file_type().is_symlink() {
+            let target = fs::read_link(&path).with(&path)?;
+            header.set_size(0);
+            builder
+                .append_link(&mut header, &name, target)
+                .with(&path)?;
+        } else if meta.is_dir() {
+            builder.append_dir(&name, &path).with(&path)?;
+            append_dir(builder, prefix, &path, root, files, verbose)?;
+        } else if meta.is_file() {
+            let mut reader = HashReader::new(fs::File::open(&path).with(&path)?);
+            builder
+                .append_data(&mut header, &name, &mut reader)
+                .with(&path)?;
+            files.insert(rel.to_string_lossy().into_owned(), reader.checksum());
+        }
+    }
+    Ok(())
+}
+
+/// Decompressed input by the magic number
+fn decoder(path: &Path) -> Result<Box<dyn Read>> {
+    const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+    const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+    let mut magic = [0u8; 6];
+    let mut file = fs::File::open(path).with(path)?;
+    let n = file.read(&mut magic).with(path)?;
+    let file = fs::File::open(path).with(path)?;
+    Ok(if magic[..n].starts_with(XZ_MAGIC) {
+        Box::new(xz2::read::XzDecoder::new_multi_decoder(io::BufReader::new(
+            file,
+        )))
+    } else if magic[..n].starts_with(ZSTD_MAGIC) {
+        Box::new(zstd::Decoder::new(file).with(path)?)
+    } else {
+        // Uncompressed tar
+        Box::new(io::BufReader::new(file))
+    })
+}
+
+/// Build unpacked by [expand]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expanded {
+    /// Name of the top-level directory
+    pub name: String,
+    pub manifest: Option<Manifest>,
+}
+
+fn invalid_archive(archive: &Path, message: impl Into<String>) -> Error {
+    Error::InvalidArchive {
+        path: archive.into(),
+        message: message.into(),
+    }
+}
+
+/// Normal components of a relative path after resolving `.` and `..`,
+/// or `None` if the path is absolute or escapes from its root
+fn normalize(path: &Path) -> Option<Vec<&std::ffi::OsStr>> {
+    let mut normalized = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop()?;
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(normalized)
+}
+
+/// Check that an entry stays in the single top-level directory of the archive, and returns the directory
+fn check_entry(archive: &Path, entry: &tar::Entry<impl Read>) -> Result<String> {
+    let path = entry.path().with(archive)?;
+    if path
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(invalid_archive(
+            archive,
+            format!("Absolute path or path traversal: {}", path.display()),
+        ));
+    }
+    let components = normalize(&path).unwrap_or_default();
+    let root = match components.first() {
+        Some(root) => root.to_string_lossy().into_owned(),
+        None => return Err(invalid_archive(archive, "Empty path")),
+    };
+    match entry.header().entry_type() {
+        tar::EntryType::Directory => {}
+        tar::EntryType::Regular | tar::EntryType::GNUSparse if components.len() > 1 => {}
+        tar::EntryType::Symlink | tar::EntryType::Link if components.len() > 1 => {
+            let target = entry
+                .link_name()
+                .with(archive)?
+                .ok_or_else(|| invalid_archive(archive, "Link without target"))?;
+            let resolved = if entry.header().entry_type() == tar::EntryType::Symlink {
+                path.parent().expect("path in root").join(&target)
+            } else {
+                target.to_path_buf()
+            };
+            let inside =
+                normalize(&resolved).is_some_and(|c| c.len() > 1 && c[0].to_string_lossy() == root);
+            if target.is_absolute() || !inside {
+                return Err(invalid_archive(
+                    archive,
+                    format!(
+                        "Link pointing outside of the build: {} -> {}",
+                        path.display(),
+                        target.display()
+                    ),
+                ));
+            }
+        }
+        entry_type => {
+            return Err(invalid_archive(
+                archive,
+                format!("Unexpected {:?} entry: {}", entry_type, path.display()),
+            ))
+        }
+    }
+    Ok(root)
+}
+
+/// Unpack an archive into `dest`, where every entry must be in a single top-level directory
+/// without absolute paths, path traversal, and links pointing outside of it
+pub fn expand(archive: &Path, dest: &Path, verbose: bool) -> Result<Expanded> {
+    let mut tar = tar::Archive::new(decoder(archive)?);
+    tar.set_preserve_permissions(true);
+    let mut name: Option<String> = None;
+    let mut manifest = None;
+    for entry in tar.entries().with(archive)? {
+        let mut entry = entry.with(archive)?;
+        let path = entry.path().with(archive)?.into_owned();
+        if path == Path::new(MANIFEST_FN) {
+            let mut content = String::new();
+            entry.read_to_string(&mut content).with(archive)?;
+            manifest = Some(toml::from_str(&content)?);
+            continue;
+        }
+        let root = check_entry(archive, &entry)?;
+        match &name {
+            Some(name) if *name != root => {
+                return Err(invalid_archive(
+                    archive,
+                    format!("Multiple top-level directories: {} and {}", name, root),
+                ))
+            }
+            Some(_) => {}
+            None => name = Some(root),
+        }
+        if verbose {
+            println!("{}", path.display());
+        }
+        entry.unpack_in(dest).with(dest.join(&path))?;
+    }
+    let name = name.ok_or_else(|| invalid_archive(archive, "No build in the archive"))?;
+    let root = dest.join(&name);
+    let root = fs::canonicalize(&root).with(&root)?;
+    check_links(archive, &root, &root)?;
+    Ok(Expanded { name, manifest })
+}
+
+/// Check that the unpacked symbolic links resolve into the root.
+/// The lexical check of each entry does not follow links, e.g. `up -> ..` and `up/../..`.
+fn check_links(archive: &Path, root: &Path, dir: &Path) -> Result<()> {
+    for entry in fs::read_dir(dir).with(dir)? {
+        let path = entry.with(dir)?.path();
+        let file_type = fs::symlink_metadata(&path).with(&path)?.file_type();
+        if file_type.is_dir() {
+            check_links(archive, root, &path)?;
+        } else if file_type.is_symlink() {
+            let inside = match fs::canonicalize(&path) {
+                Ok(resolved) => resolved.starts_with(root),
+                // Dangling link cannot be resolved, allowed only without `..`
+                Err(_) => fs::read_link(&path)
+                    .with(&path)?
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_) | Component::CurDir)),
+            };
+            if !inside {
+                return Err(invalid_archive(
+                    archive,
+                    format!("Link resolved outside of the build: {}", path.display()),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Options of [import]
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ImportOptions {
+    /// Build name instead of the name in the archive
+    pub name: Option<String>,
+    /// Overwrite an existing build of the same name
+    pub force: bool,
+    /// Fingerprint hash of the build expected by the manifest, see [fingerprint](../fingerprint/index.html) module
+    pub fingerprint: Option<String>,
+}
+
+/// Build imported by [import]
+#[derive(Debug)]
+pub struct Imported {
+    pub build: Build,
+    /// Rewritten paths if the build has been archived in another prefix
+    pub relocation: Option<Relocation>,
+}
+
+/// Import an archive as a build in the data directory.
+///
+/// The archive is unpacked into a staging directory, checked by `{archive}.sha256` if exists
+/// and by the embedded manifest, and relocated from the old prefix there,
+/// see [relocate](../relocate/index.html) module.
+/// Then it is moved into the data directory. A build replaced by `force` is moved aside,
+/// and removed only after the new build is in place.
+pub fn import(archive: &Path, options: &ImportOptions, verbose: bool) -> Result<Imported> {
+    import_into(archive, &data_dir()?, options, verbose)
+}
+
+pub(crate) fn import_into(
+    archive: &Path,
+    data_dir: &Path,
+    options: &ImportOptions,
+    verbose: bool,
+) -> Result<Imported> {
+    if !archive.is_file() {
+        return Err(invalid_archive(archive, "Archive does not exist"));
+    }
+    check_sidecar_checksum(archive)?;
+    let staging = tempfile::Builder::new()
+        .prefix(".llvmmgmt-import-")
+        .tempdir_in(data_dir)
+        .with(data_dir)?;
+    let expanded = expand(archive, staging.path(), verbose)?;
+    let root = staging.path().join(&expanded.name);
+    match &expanded.manifest {
+        Some(manifest) => {
+            if manifest.name != expanded.name {
+                return Err(invalid_archive(
+                    archive,
+                    format!(
+                        "Build {} is archived as {} in the manifest",
+                        expanded.name, manifest.name
+                    ),
+                ));
+            }
+            check_files(archive, &root, &manifest.files)?;
+        }
+        None => warn!(
+            "No manifest in {}, files are not verified",
+            archive.display()
+        ),
+    }
+    if let Some(expected) = &options.fingerprint {
+        let actual = expanded
+            .manifest
+            .as_ref()
+            .and_then(|manifest| manifest.info.as_ref())
+            .and_then(|info| info.fingerprint.as_ref())
+            .map(|fingerprint| fingerprint.hash());
+        if actual.as_ref() != Some(expected) {
+            return Err(invalid_archive(
+                archive,
+                format!(
+                    "Fingerprint {} is expected, but got {}",
+                    expected,
+                    actual.as_deref().unwrap_or("none")
+                ),
+            ));
+        }
+    }
+    let is_llvm = root.join("bin").is_dir()
+        && (expanded.manifest.is_some()
+            || root.join(".llvmmgmt-build.toml").exists()
+            || root.join("bin/llvm-config").exists()
+            || root.join("lib/cmake/llvm").is_dir());
+    if !is_llvm {
+        return Err(invalid_archive(
+            archive,
+            format!("{} is not an LLVM build", expanded.name),
+        ));
+    }
+
+    let name = options.name.as_deref().unwrap_or(&expanded.name);
+    if name == "system" || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(Error::InvalidBuild {
+            name: name.into(),
+            message: "Invalid build name".into(),
+        });
+    }
+    let prefix = data_dir.join(name);
+    if prefix.exists() && !options.force {
+        return Err(Error::InvalidBuild {
+            name: name.into(),
+            message: "Build already exists, use --force to overwrite".into(),
+        });
+    }
+
+    let from = match expanded.manifest {
+        Some(manifest) => Some(manifest.prefix),
+        None => relocate::detect_prefix(&root, &expanded.name, &prefix)?,
+    };
+    let relocation = match from {
+        Some(from) if from != prefix && !from.as_os_str().is_empty() => {
+            Some(relocate::relocate(&root, &from, &prefix)?)
+        }
+        _ => None,
+    };
+
+    let replaced = if prefix.exists() {
+        let replaced = tempfile::Builder::new()
+            .prefix(".llvmmgmt-replaced-")
+            .tempdir_in(data_dir)
+            .with(data_dir)?;
+        let old = replaced.path().join(name);
+        fs::rename(&prefix, &old).with(&prefix)?;
+        Some((replaced, old))
+    } else {
+        None
+    };
+    if let Err(e) = fs::rename(&root, &prefix) {
+        if let Some((_, old)) = &replaced {
+            if let Err(e) = fs::rename(old, &prefix) {
+                warn!("Cannot restore {}: {}", prefix.display(), e);
+            }
+        }
+        return Err(e).with(&prefix);
+    }
+    if let Some((replaced, old)) = replaced {
+        info!("Remove replaced build: {}", prefix.display());
+        fs::remove_dir_all(&old).with(&old)?;
+        drop(replaced);
+    }
+    info!("Imported {} as {}", archive.display(), name);
+    Ok(Imported {
+        build: Build::from_path(&prefix),
+        relocation,
+    })
+}
+
+/// Check the archive by `sha256sum` output in `{archive}.sha256`
+fn check_sidecar_checksum(archive: &Path) -> Result<()> {
+    let sidecar = PathBuf::from(format!("{}.sha256", archive.display()));
+    if !sidecar.exists() {
+        return Ok(());
+    }
+    let content = fs::read_to_string(&sidecar).with(&sidecar)?;
+    let expected = content.split_whitespace().next().unwrap_or_default();
+    let actual = file_checksum(archive)?;
+    if !expected.eq_ignore_ascii_case(&actual) {
+        return Err(invalid_archive(
+            archive,
+            format!(
+                "Checksum mismatch with {}: {} is expected, but got {}",
+                sidecar.display(),
+                expected,
+                actual
+            ),
+        ));
+    }
+    info!("Checksum verified by {}", sidecar.display());
+    Ok(())
+}
+
+/// Check the unpacked files by the checksums in the manifest
+fn check_files(archive: &Path, root: &Path, files: &BTreeMap<String, String>) -> Result<()> {
+    let mut unpacked = Vec::new();
+    regular_files(root, root, &mut unpacked)?;
+    let mut errors = Vec::new();
+    for rel in &unpacked {
+        if !files.contains_key(rel) {
+            errors.push(format!("{} is not in the manifest", rel));
+        }
+    }
+    for (rel, checksum) in files {
+        let path = root.join(rel);
+        if !path.is_file() {
+            errors.push(format!("{} is missing", rel));
+        } else if file_checksum(&path)? != *checksum {
+            errors.push(format!("{} has a wrong checksum", rel));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(invalid_archive(archive, errors.join(", ")))
+    }
+}
+
+fn regular_files(root: &Path, dir: &Path, files: &mut Vec<String>) -> Result<()> {
+    for entry in fs::read_dir(dir).with(dir)? {
+        let path = entry.with(dir)?.path();
+        let meta = fs::symlink_metadata(&path).with(&path)?;
+        if meta.is_dir() {
+            regular_files(root, &path, files)?;
+        } else if meta.is_file() {
+            let rel = path.strip_prefix(root).expect("file in root");
+            files.push(rel.to_string_lossy().into_owned());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fake_build;
+
+    #[test]
+    fn archive_roundtrip() -> Result<()> {
+        for compression in [Compression::Xz, Compression::Zstd] {
+            let src = tempfile::TempDir::new().with("/tmp")?;
+            let dest = tempfile::TempDir::new().with("/tmp")?;
+            let build = fake_build(src.path(), "18.1.8")?;
+            let output = src
+                .path()
+                .join(format!("18.1.8.{}", compression.extension()));
+            let manifest = create(&build, &output, compression, false)?;
+            assert_eq!(Compression::from_path(&output), Some(compression));
+            assert_eq!(
+                manifest.files.keys().collect::<Vec<_>>(),
+                ["bin/clang", "lib/cmake/llvm/LLVMConfig.cmake"]
+            );
+            assert_eq!(
+                manifest.files["bin/clang"],
+                file_checksum(&build.prefix().join("bin/clang"))?
+            );
+
+            let expanded = expand(&output, dest.path(), false)?;
+            assert_eq!(expanded.name, "18.1.8");
+            assert_eq!(expanded.manifest, Some(manifest));
+            let prefix = dest.path().join("18.1.8");
+            assert_eq!(
+                fs::read_to_string(prefix.join("lib/cmake/llvm/LLVMConfig.cmake")).with(&prefix)?,
+                format!(
+                    "set(LLVM_CMAKE_DIR {}/lib/cmake/llvm)\n",
+                    build.prefix().display()
+                )
+            );
+            #[cfg(unix)]
+            assert_eq!(
+                fs::read_link(prefix.join("bin/clang++")).with(&prefix)?,
+                Path::new("clang")
+            );
+            assert!(!dest.path().join(MANIFEST_FN).exists());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn manifest_of_build() -> Result<()> {
+        let src = tempfile::TempDir::new().with("/tmp")?;
+        let build = fake_build(src.path(), "18.1.8-debug")?;
+        let setting = EntrySetting {
+            build_type: crate::entry::BuildType::Debug,
+            ..Default::default()
+        };
+        BuildInfo {
+            entry: "18.1.8".into(),
+            fingerprint: Some(crate::fingerprint::Fingerprint {
+                entry: "18.1.8".into(),
+                variant: Some("debug".into()),
+                sources: Vec::new(),
+                setting: setting.clone(),
+                generator: crate::entry::CMakeGenerator::Ninja,
+                host_triple: "x86_64-linux-gnu".into(),
+                compiler: None,
+            }),
+            ..Default::default()
+        }
+        .save(build.prefix())?;
+        // The setting as built, not the current entry
+        let manifest = Manifest::new(&build)?;
+        assert_eq!(manifest.version.as_deref(), Some("18.1.8"));
+        assert_eq!(manifest.setting, Some(setting));
+        Ok(())
+    }
+
+    /// Uncompressed tar with raw paths, which `tar::Builder` refuses
+    fn raw_tar(path: &Path, entries: &[(&str, tar::EntryType, &str)]) -> Result<()> {
+        let mut builder = tar::Builder::new(fs::File::create(path).with(path)?);
+        for (name, entry_type, content) in entries {
+            let mut header = tar::Header::new_old();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_entry_type(*entry_type);
+            header.set_mode(0o644);
+            if *entry_type == tar::EntryType::Symlink {
+                header.set_link_name(content).with(path)?;
+                header.set_size(0);
+                header.set_cksum();
+                builder.append(&header, io::empty()).with(path)?;
+            } else {
+                header.set_size(content.len() as u64);
+                header.set_cksum();
+                builder.append(&header, content.as_bytes()).with(path)?;
+            }
+        }
+        builder.finish().with(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn reject_unsafe_entries() -> Result<()> {
+        use tar::EntryType::*;
+        let dir = tempfile::TempDir::new().with("/tmp")?;
+        let archive = dir.path().join("evil.tar");
+        let cases: &[&[(&str, tar::EntryType, &str)]] = &[
+            &[("18.1.8/bin/../../../evil", Regular, "x")],
+            &[("/tmp/evil", Regular, "x")],
+            &[("18.1.8/bin/clang", Symlink, "../../../../etc/passwd")],
+            &[("18.1.8/bin/clang", Symlink, "/etc/passwd")],
+            // Inside lexically, but `up/..` is `18.1.8` through the link
+            &[
+                ("18.1.8/bin/x/up", Symlink, ".."),
+                ("18.1.8/bin/x/clang", Symlink, "up/../.."),
+            ],
+            &[
+                ("18.1.8/bin/clang", Regular, "x"),
+                ("17.0.0/bin/clang", Regular, "x"),
+            ],
+            &[("evil", Regular, "x")],
+        ];
+        for entries in cases {
+            raw_tar(&archive, entries)?;
+            let dest = tempfile::TempDir::new().with("/tmp")?;
+            match expand(&archive, dest.path(), false) {
+                Err(Error::InvalidArchive { .. }) => {}
+                result => panic!("{:?} is not rejected: {:?}", entries, result),
+            }
+            assert!(!dir.path().join("evil").exists());
+            assert!(!dest
+                .path()
+                .parent()
+                .expect("temporary dir")
+                .join("evil")
+                .exists());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn import_build() -> Result<()> {
+        let src = tempfile::TempDir::new().with("/tmp")?;
+        let data_dir = tempfile::TempDir::new().with("/tmp")?;
+        let build = fake_build(src.path(), "18.1.8")?;
+        let archive = src.path().join("18.1.8.tar.xz");
+        create(&build, &archive, Compression::Xz, false)?;
+
+        let imported = import_into(&archive, data_dir.path(), &Default::default(), false)?.build;
+        assert_eq!(imported.name(), "18.1.8");
+        assert!(imported.prefix().join("bin/clang").exists());
+        // Existing build
+        assert!(matches!(
+            import_into(&archive, data_dir.path(), &Default::default(), false),
+            Err(Error::InvalidBuild { .. })
+        ));
+        let force = ImportOptions {
+            force: true,
+            ..Default::default()
+        };
+        let stale = data_dir.path().join("18.1.8/bin/stale");
+        fs::write(&stale, "").with(&stale)?;
+        import_into(&archive, data_dir.path(), &force, false)?;
+        assert!(!stale.exists());
+        let renamed = ImportOptions {
+            name: Some("llvm-18".into()),
+            ..Default::default()
+        };
+        let imported = import_into(&archive, data_dir.path(), &renamed, false)?;
+        assert_eq!(imported.build.prefix(), data_dir.path().join("llvm-18"));
+        // Relocated from the prefix in the manifest
+        let relocation = imported.relocation.unwrap();
+        assert_eq!(relocation.from, build.prefix());
+        assert_eq!(
+            relocation.changed,
+            [("lib/cmake/llvm/LLVMConfig.cmake".to_string(), 1)]
+        );
+        let config = imported
+            .build
+            .prefix()
+            .join("lib/cmake/llvm/LLVMConfig.cmake");
+        assert_eq!(
+            fs::read_to_string(&config).with(&config)?,
+            format!(
+                "set(LLVM_CMAKE_DIR {}/lib/cmake/llvm)\n",
+                imported.build.prefix().display()
+            )
+        );
+
+        // Staging directories are removed
+        let mut names: Vec<_> = fs::read_dir(data_dir.path())
+            .with(data_dir.path())?
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["18.1.8", "llvm-18"]);
+
+        // Sidecar checksum
+        let sidecar = src.path().join("18.1.8.tar.xz.sha256");
+        fs::write(&sidecar, format!("{}  18.1.8.tar.xz\n", "0".repeat(64))).with(&sidecar)?;
+        assert!(matches!(
+            import_into(&archive, data_dir.path(), &force, false),
+            Err(Error::InvalidArchive { .. })
+        ));
+        // The existing build is kept
+        assert!(data_dir.path().join("18.1.8/bin/clang").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn tampered_manifest() -> Result<()> {
+        let dir = tempfile::TempDir::new().with("/tmp")?;
+        let data_dir = tempfile::TempDir::new().with("/tmp")?;
+        let manifest = Manifest {
+            name: "18.1.8".into(),
+            files: [("bin/clang".to_string(), "0".repeat(64))]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let archive = dir.path().join("18.1.8.tar");
+        raw_tar(
+            &archive,
+            &[
+                ("18.1.8/bin/clang", tar::EntryType::Regular, "x"),
+                (MANIFEST_FN, tar::EntryType::Regular, &manifest.to_toml()?),
+            ],
+        )?;
+        match import_into(&archive, data_dir.path(), &Default::default(), false) {
+            Err(Error::InvalidArchive { message, .. }) => {
+                assert_eq!(message, "bin/clang has a wrong checksum")
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert!(!data_dir.path().join("18.1.8").exists());
+
+        // Not a build of LLVM
+        raw_tar(
+            &archive,
+            &[("photos/cat.jpg", tar::EntryType::Regular, "x")],
+        )?;
+        assert!(matches!(
+            import_into(&archive, data_dir.path(), &Default::default(), false),
+            Err(Error::InvalidArchive { .. })
+        ));
+        Ok(())
+    }
+}
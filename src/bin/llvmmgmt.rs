@@ -37,6 +37,29 @@ enum LLVMMgmt {
     #[structopt(name = "install", about = "Downloads and builds a specific LLVM version")]
     Install {
         version: String,
+        /// Build variant, e.g. debug or asserts
+        #[structopt(long)]
+        variant: Option<String>,
+        /// Number of compile jobs (default: computed from cores and memory)
+        #[structopt(long, short)]
+        jobs: Option<usize>,
+        /// Number of link jobs (default: computed from memory)
+        #[structopt(long)]
+        link_jobs: Option<usize>,
+        /// Build profiles applied on the entry, e.g. dev or minimal
+        #[structopt(long, use_delimiter = true)]
+        profile: Vec<String>,
+        #[structopt(flatten)]
+        priority: PriorityOpt,
+        /// Compile even if the binary cache has the build
+        #[structopt(long)]
+        no_cache: bool,
+        /// Replace an existing build by the build in the binary cache
+        #[structopt(long)]
+        force: bool,
+        /// Print the full build output instead of progress
+        #[structopt(long, short)]
+        verbose: bool,
     },
 
     #[structopt(name = "use", about = "Sets the current LLVM version")]
@@ -44,6 +67,9 @@ enum LLVMMgmt {
         version: String,
         #[structopt(long)]
         global: bool,
+        /// Use the build of a variant, e.g. `18.1.8-asserts` for `use 18.1.8 --variant asserts`
+        #[structopt(long)]
+        variant: Option<String>,
     },
     #[structopt(name = "current", about = "Shows the currently active LLVM version")]
     Current,
@@ -73,12 +99,24 @@ enum LLVMMgmt {
     #[structopt(name = "archive", about = "Archive a build")]
     Archive {
         name: String,
+        /// Path of the archive (default: {name}.tar.xz in the current directory)
+        #[structopt(long, short)]
+        output: Option<PathBuf>,
+        /// xz or zstd (default: from the extension of the output, or xz)
+        #[structopt(long)]
+        compression: Option<archive::Compression>,
         #[structopt(long, short)]
         verbose: bool,
     },
-    #[structopt(name = "expand", about = "Expand an archived build")]
-    Expand {
+    #[structopt(name = "import", alias = "expand", about = "Imports an archived build")]
+    Import {
         archive: PathBuf,
+        /// Import under another build name
+        #[structopt(long = "as")]
+        name: Option<String>,
+        /// Overwrite an existing build of the same name
+        #[structopt(long)]
+        force: bool,
         #[structopt(long, short)]
         verbose: bool,
     },
@@ -86,6 +124,77 @@ enum LLVMMgmt {
     Uninstall {
         name: String,
     },
+    #[structopt(name = "stats", about = "Shows timing and statistics of a build")]
+    Stats { name: String },
+    #[structopt(name = "outdated", about = "Lists builds whose entries have changed")]
+    Outdated {
+        /// Builds to check (default: all builds)
+        names: Vec<String>,
+        /// Do not check the upstream heads of the sources
+        #[structopt(long)]
+        offline: bool,
+    },
+    #[structopt(name = "cache", about = "Shares builds by the binary cache")]
+    Cache(CacheCmd),
+    #[structopt(name = "verify", about = "Checks that a build works")]
+    Verify {
+        name: String,
+        /// Print the report in JSON
+        #[structopt(long)]
+        json: bool,
+    },
+}
+
+/// Priority and resource limits of build processes, which override `priority` in config.toml
+#[derive(StructOpt, Debug)]
+struct PriorityOpt {
+    /// Niceness of build processes, from -20 to 19
+    #[structopt(long)]
+    nice: Option<i32>,
+    /// I/O priority of build processes, `idle` or `best-effort:{0-7}`
+    #[structopt(long)]
+    ionice: Option<priority::IoPriority>,
+    /// Memory ceiling of the whole build by cgroup v2, e.g. 24G
+    #[structopt(long)]
+    memory_max: Option<priority::MemorySize>,
+}
+
+impl From<PriorityOpt> for priority::Priority {
+    fn from(opt: PriorityOpt) -> Self {
+        priority::Priority {
+            nice: opt.nice,
+            ionice: opt.ionice,
+            memory_max: opt.memory_max,
+        }
+    }
+}
+
+#[derive(StructOpt, Debug)]
+enum CacheCmd {
+    #[structopt(name = "push", about = "Upload a build to the binary cache")]
+    Push {
+        name: String,
+        #[structopt(long, short)]
+        verbose: bool,
+    },
+    #[structopt(
+        name = "pull",
+        about = "Fetch the build of an entry from the binary cache"
+    )]
+    Pull {
+        name: String,
+        /// Build variant, e.g. debug or asserts
+        #[structopt(long)]
+        variant: Option<String>,
+        /// Build profiles applied on the entry, e.g. dev or minimal
+        #[structopt(long, use_delimiter = true)]
+        profile: Vec<String>,
+        /// Overwrite an existing build of the same name
+        #[structopt(long)]
+        force: bool,
+        #[structopt(long, short)]
+        verbose: bool,
+    },
 }
 
 #[derive(StructOpt, Debug)]
@@ -93,8 +202,67 @@ enum EntryCmd {
     #[structopt(name = "build", about = "Build a specific entry")]
     Build {
         name: String,
-        #[structopt(long, default_value = "0")]
-        nproc: usize,
+        /// Number of compile jobs (default: computed from cores and memory)
+        #[structopt(long, short, alias = "nproc")]
+        jobs: Option<usize>,
+        /// Number of link jobs (default: computed from memory)
+        #[structopt(long)]
+        link_jobs: Option<usize>,
+        /// Build variant, e.g. debug or asserts
+        #[structopt(long)]
+        variant: Option<String>,
+        /// Build profiles applied on the entry, e.g. dev or minimal
+        #[structopt(long, use_delimiter = true)]
+        profile: Vec<String>,
+        /// Build and install only these targets, e.g. clang-format,FileCheck
+        #[structopt(long, use_delimiter = true)]
+        targets: Vec<String>,
+        /// Install the targets into a full build
+        #[structopt(long)]
+        force: bool,
+        #[structopt(flatten)]
+        priority: PriorityOpt,
+        /// Print the full build output instead of progress
+        #[structopt(long, short)]
+        verbose: bool,
+    },
+    #[structopt(
+        name = "test",
+        about = "Run a test suite in the build directory of an entry"
+    )]
+    Test {
+        name: String,
+        /// Build variant, e.g. debug or asserts
+        #[structopt(long)]
+        variant: Option<String>,
+        /// Build profiles applied on the entry, e.g. dev or minimal
+        #[structopt(long, use_delimiter = true)]
+        profile: Vec<String>,
+        /// Test target, e.g. check-clang
+        #[structopt(long, default_value = "check-llvm")]
+        suite: String,
+        /// Run only the tests matching the regex
+        #[structopt(long)]
+        filter: Option<String>,
+        /// Number of compile jobs for the test dependencies
+        #[structopt(long, short)]
+        jobs: Option<usize>,
+        /// Print the full test output instead of progress
+        #[structopt(long, short)]
+        verbose: bool,
+    },
+    #[structopt(name = "fingerprint", about = "Show the fingerprint of an entry")]
+    Fingerprint {
+        name: String,
+        /// Build variant, e.g. debug or asserts
+        #[structopt(long)]
+        variant: Option<String>,
+        /// Build profiles applied on the entry, e.g. dev or minimal
+        #[structopt(long, use_delimiter = true)]
+        profile: Vec<String>,
+        /// Print the resolved fields in JSON
+        #[structopt(long)]
+        json: bool,
     },
     #[structopt(name = "clean-cache", about = "Clean cache directory for an entry")]
     CleanCache {
@@ -138,16 +306,60 @@ fn main() -> error::Result<()> {
 
     let opt = LLVMMgmt::from_args();
     match opt {
-        LLVMMgmt::Install { version } => {
-            let entry = entry::load_entry(&version)?;
-            let nproc = num_cpus::get();
-            entry.checkout()?;
-            entry.build(nproc)?;
+        LLVMMgmt::Install {
+            version,
+            variant,
+            jobs,
+            link_jobs,
+            profile,
+            priority,
+            no_cache,
+            force,
+            verbose,
+        } => {
+            let entry = entry::load_entry_with(&version, variant.as_deref(), &profile)?;
+            // Checked out first, which identifies Tar archives and local changes in the fingerprint
+            let mut timer = bootstrap::StageTimer::default();
+            timer.run("checkout", || entry.checkout())?;
+            if !no_cache {
+                if let Some(cache) = binary_cache::BinaryCache::from_config()? {
+                    match cache.fetch(&entry, force, verbose) {
+                        Ok(Some(imported)) => {
+                            if let Some(relocation) = &imported.relocation {
+                                print!("{relocation}");
+                            }
+                            println!("{}", imported.build.prefix().display());
+                            return Ok(());
+                        }
+                        Ok(None) => {}
+                        Err(e) => eprintln!("Binary cache {cache} is not used: {e}"),
+                    }
+                }
+            }
+            entry.build_with_timer(
+                entry.jobs(jobs, link_jobs)?,
+                entry.priority(priority.into())?,
+                verbose,
+                timer,
+            )?;
             Ok(())
         }
 
-        LLVMMgmt::Use { version, global } => {
-            let build = get_existing_build(&version)?;
+        LLVMMgmt::Use {
+            version,
+            global,
+            variant,
+        } => {
+            let build = get_existing_build(&variant::build_name(&version, variant.as_deref()))?;
+            if let Some(info) = build.info()? {
+                if !info.is_runnable() {
+                    eprintln!(
+                        "Build '{}' is cross compiled for {}, and does not run on this host",
+                        build.name(),
+                        info.host_triple.unwrap_or_default()
+                    );
+                }
+            }
             if global {
                 build.set_global()?;
             } else {
@@ -180,7 +392,32 @@ fn main() -> error::Result<()> {
             } else {
                 let builds = build::builds()?;
                 for b in &builds {
-                    println!("{}", b.name());
+                    let mut notes = Vec::new();
+                    if b.name() != "system" {
+                        notes.push(stats::format_size(stats::disk_usage(b.prefix())));
+                    }
+                    if let Some(info) = b.info()? {
+                        if let Some(variant) = &info.variant {
+                            notes.push(format!("variant {} of {}", variant, info.entry));
+                        }
+                        if info.is_partial() {
+                            notes.push(format!("partial: {}", info.targets.join(", ")));
+                        }
+                        if !info.components.is_empty() {
+                            notes.push(format!("components: {}", info.components.join(", ")));
+                        }
+                        if !info.is_runnable() {
+                            notes.push(format!(
+                                "cross compiled for {}, not runnable",
+                                info.host_triple.unwrap_or_default()
+                            ));
+                        }
+                    }
+                    if notes.is_empty() {
+                        println!("{}", b.name());
+                    } else {
+                        println!("{} ({})", b.name(), notes.join(", "));
+                    }
                 }
                 Ok(())
             }
@@ -213,9 +450,59 @@ fn main() -> error::Result<()> {
             Ok(())
         }
         LLVMMgmt::Entry(cmd) => match cmd {
-            EntryCmd::Build { name, nproc } => {
-                let entry = entry::load_entry(&name)?;
-                entry.build(nproc)?;
+            EntryCmd::Build {
+                name,
+                jobs,
+                link_jobs,
+                variant,
+                profile,
+                targets,
+                force,
+                priority,
+                verbose,
+            } => {
+                let mut entry = entry::load_entry_with(&name, variant.as_deref(), &profile)?;
+                if !targets.is_empty() {
+                    entry.setting_mut().build_targets = targets;
+                }
+                if !force {
+                    entry.check_partial()?;
+                }
+                entry.build(
+                    entry.jobs(jobs, link_jobs)?,
+                    entry.priority(priority.into())?,
+                    verbose,
+                )?;
+                Ok(())
+            }
+            EntryCmd::Test {
+                name,
+                variant,
+                profile,
+                suite,
+                filter,
+                jobs,
+                verbose,
+            } => {
+                let entry = entry::load_entry_with(&name, variant.as_deref(), &profile)?;
+                let report =
+                    entry.test(&suite, filter.as_deref(), entry.jobs(jobs, None)?, verbose)?;
+                print!("{}", report);
+                report.check()
+            }
+            EntryCmd::Fingerprint {
+                name,
+                variant,
+                profile,
+                json,
+            } => {
+                let entry = entry::load_entry_with(&name, variant.as_deref(), &profile)?;
+                let fingerprint = entry.fingerprint()?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&fingerprint)?);
+                } else {
+                    println!("{}", fingerprint.hash());
+                }
                 Ok(())
             }
             EntryCmd::CleanCache { name } => {
@@ -239,8 +526,7 @@ fn main() -> error::Result<()> {
                 Ok(())
             }
             EntryCmd::SetBuildType { name, build_type } => {
-                let mut entry = entry::load_entry(&name)?;
-                entry.set_build_type(build_type)?;
+                let entry = entry::load_entry(&name)?;
                 let global_toml = config_dir()?.join(ENTRY_TOML);
                 let mut entries = entry::load_entry_toml(
                     &fs::read_to_string(&global_toml).with(&global_toml)?,
@@ -248,7 +534,8 @@ fn main() -> error::Result<()> {
                 let mut found = false;
                 for e in entries.iter_mut() {
                     if e.name() == entry.name() {
-                        *e = entry;
+                        // Profiles applied to the loaded entry are not written back
+                        e.set_build_type(build_type)?;
                         found = true;
                         break;
                     }
@@ -261,13 +548,12 @@ fn main() -> error::Result<()> {
                 for e in entries {
                     map.insert(e.name().to_string(), e.setting().clone());
                 }
-                let toml_str = toml::to_string(&map)?;
+                let toml_str = toml::to_string(&toml::Value::try_from(&map)?)?;
                 fs::write(&global_toml, toml_str).with(&global_toml)?;
                 Ok(())
             }
             EntryCmd::SetGenerator { name, generator } => {
-                let mut entry = entry::load_entry(&name)?;
-                entry.set_builder(&generator)?;
+                let entry = entry::load_entry(&name)?;
                 let global_toml = config_dir()?.join(ENTRY_TOML);
                 let mut entries = entry::load_entry_toml(
                     &fs::read_to_string(&global_toml).with(&global_toml)?,
@@ -275,7 +561,8 @@ fn main() -> error::Result<()> {
                 let mut found = false;
                 for e in entries.iter_mut() {
                     if e.name() == entry.name() {
-                        *e = entry;
+                        // Profiles applied to the loaded entry are not written back
+                        e.set_builder(&generator)?;
                         found = true;
                         break;
                     }
@@ -288,7 +575,7 @@ fn main() -> error::Result<()> {
                 for e in entries {
                     map.insert(e.name().to_string(), e.setting().clone());
                 }
-                let toml_str = toml::to_string(&map)?;
+                let toml_str = toml::to_string(&toml::Value::try_from(&map)?)?;
                 fs::write(&global_toml, toml_str).with(&global_toml)?;
                 Ok(())
             }
@@ -303,13 +590,40 @@ fn main() -> error::Result<()> {
             println!("{}", build.prefix().display());
             Ok(())
         }
-        LLVMMgmt::Archive { name, verbose } => {
-            let build = Build::from_name(&name)?;
-            build.archive(verbose)?;
+        LLVMMgmt::Archive {
+            name,
+            output,
+            compression,
+            verbose,
+        } => {
+            let build = get_existing_build(&name)?;
+            let compression = compression
+                .or_else(|| output.as_deref().and_then(archive::Compression::from_path))
+                .unwrap_or(archive::Compression::Xz);
+            let output = match output {
+                Some(output) => output,
+                None => PathBuf::from(format!("{}.{}", name, compression.extension())),
+            };
+            build.archive(&output, compression, verbose)?;
+            println!("{}", output.display());
             Ok(())
         }
-        LLVMMgmt::Expand { archive, verbose } => {
-            build::expand(&archive, verbose)?;
+        LLVMMgmt::Import {
+            archive,
+            name,
+            force,
+            verbose,
+        } => {
+            let options = archive::ImportOptions {
+                name,
+                force,
+                ..Default::default()
+            };
+            let imported = build::expand(&archive, &options, verbose)?;
+            if let Some(relocation) = &imported.relocation {
+                print!("{relocation}");
+            }
+            println!("{}", imported.build.prefix().display());
             Ok(())
         }
         LLVMMgmt::Uninstall { name } => {
@@ -317,6 +631,108 @@ fn main() -> error::Result<()> {
             build.uninstall()?;
             Ok(())
         }
+        LLVMMgmt::Stats { name } => {
+            let build = get_existing_build(&name)?;
+            let info = build.info()?.unwrap_or_default();
+            let stats = info.stats.ok_or_else(|| Error::InvalidBuild {
+                name: name.clone(),
+                message: "Statistics are not recorded".into(),
+            })?;
+            match stats.jobs {
+                Some(jobs) => println!(
+                    "Build {} ({:?}, {} compile and {} link jobs)",
+                    name, stats.build_type, jobs.compile, jobs.link
+                ),
+                None => println!("Build {} ({:?})", name, stats.build_type),
+            }
+            print!("{stats}");
+            Ok(())
+        }
+        LLVMMgmt::Outdated { names, offline } => {
+            let builds = if names.is_empty() {
+                build::builds()?
+                    .into_iter()
+                    .filter(|b| b.name() != "system")
+                    .collect()
+            } else {
+                names
+                    .iter()
+                    .map(|name| get_existing_build(name))
+                    .collect::<Result<Vec<_>>>()?
+            };
+            let mut up_to_date = true;
+            for b in &builds {
+                let report = outdated::check(b, offline);
+                if report.is_outdated() || report.unknown.is_some() {
+                    up_to_date = false;
+                    print!("{report}");
+                }
+            }
+            if up_to_date {
+                println!("All builds are up to date");
+            }
+            Ok(())
+        }
+        LLVMMgmt::Cache(cmd) => {
+            let cache = match binary_cache::BinaryCache::from_config()? {
+                Some(cache) => cache,
+                None => {
+                    eprintln!("No binary cache. Please set binary_cache in $XDG_CONFIG_HOME/llvmmgmt/config.toml");
+                    exit(1);
+                }
+            };
+            match cmd {
+                CacheCmd::Push { name, verbose } => {
+                    let build = get_existing_build(&name)?;
+                    let hash = cache.push(&build, verbose)?;
+                    println!("{hash}");
+                    Ok(())
+                }
+                CacheCmd::Pull {
+                    name,
+                    variant,
+                    profile,
+                    force,
+                    verbose,
+                } => {
+                    let entry = entry::load_entry_with(&name, variant.as_deref(), &profile)?;
+                    // Tar archives are identified by the downloaded files
+                    if !entry.src_dir()?.exists() {
+                        entry.checkout()?;
+                    }
+                    match cache.fetch(&entry, force, verbose)? {
+                        Some(imported) => {
+                            if let Some(relocation) = &imported.relocation {
+                                print!("{relocation}");
+                            }
+                            println!("{}", imported.build.prefix().display());
+                            Ok(())
+                        }
+                        None => Err(Error::InvalidBuild {
+                            name: entry.build_name(),
+                            message: format!("Not found in the binary cache {cache}"),
+                        }),
+                    }
+                }
+            }
+        }
+        LLVMMgmt::Verify { name, json } => {
+            let build = get_existing_build(&name)?;
+            let report = verify::verify(&build)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print!("{report}");
+            }
+            if report.ok {
+                Ok(())
+            } else {
+                Err(Error::InvalidBuild {
+                    name,
+                    message: "Verification failed".into(),
+                })
+            }
+        }
     }
 }
 
@@ -0,0 +1,385 @@
+//! Shared binary cache of builds
+//!
+//! Builds of the same [fingerprint](../fingerprint/index.html) are interchangeable,
+//! so a team can build an entry once and share it. The binary cache is a directory, e.g. on NFS,
+//! or an HTTP server accepting `PUT` and `GET`, set in config.toml:
+//!
+//! ```toml
+//! binary_cache = "/nfs/llvmmgmt-cache"  # or "https://cache.example.com/llvmmgmt"
+//! ```
+//!
+//! ```shell
+//! llvmmgmt cache push 18.1.8   # upload the build
+//! llvmmgmt install 18.1.8      # fetch the build of the same fingerprint instead of compiling
+//! llvmmgmt cache pull 18.1.8   # fetch without falling back to compile
+//! ```
+//!
+//! A build is stored as `{fingerprint hash}.tar.zst` archived by [archive](../archive/index.html) module,
+//! with its `sha256sum` output in `{fingerprint hash}.tar.zst.sha256`.
+//! Fetched builds are verified and relocated like `llvmmgmt import`,
+//! and rejected if the fingerprint recorded in the archive does not match.
+//! `llvmmgmt install --no-cache` always compiles.
+//!
+//! Builds are pushed and fetched only if every source is identified by a revision, see
+//! [Fingerprint::check_sources](../fingerprint/struct.Fingerprint.html#method.check_sources).
+//! A local entry outside of Git, or a Tar source not downloaded yet, is compiled.
+//!
+//! [Hooks](../hook/index.html) do not run for a fetched build. Its files already include the changes of
+//! `post_install` made where it was pushed; scripts with other side effects, e.g. notifications, are skipped.
+
+use log::info;
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+};
+
+use crate::{
+    archive::{self, Compression, ImportOptions, Imported},
+    build::Build,
+    config::*,
+    entry::Entry,
+    error::*,
+};
+
+/// Location of the binary cache
+#[derive(Debug, Clone, PartialEq)]
+pub enum BinaryCache {
+    /// Local or network file system
+    Dir(PathBuf),
+    /// Base URL of an HTTP server
+    Http(String),
+}
+
+impl FromStr for BinaryCache {
+    type Err = Error;
+    fn from_str(location: &str) -> Result<Self> {
+        if location.starts_with("http://") || location.starts_with("https://") {
+            url::Url::parse(location).map_err(|_| Error::InvalidUrl {
+                url: location.into(),
+            })?;
+            Ok(BinaryCache::Http(location.trim_end_matches('/').into()))
+        } else {
+            Ok(BinaryCache::Dir(expand_path(location)))
+        }
+    }
+}
+
+impl fmt::Display for BinaryCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BinaryCache::Dir(dir) => write!(f, "{}", dir.display()),
+            BinaryCache::Http(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+/// File name of the archive of a fingerprint hash
+fn artifact(hash: &str) -> String {
+    format!("{}.{}", hash, Compression::Zstd.extension())
+}
+
+fn sidecar(name: &str) -> String {
+    format!("{}.sha256", name)
+}
+
+impl BinaryCache {
+    /// `binary_cache` in config.toml if set
+    pub fn from_config() -> Result<Option<Self>> {
+        Config::global()?
+            .binary_cache
+            .as_deref()
+            .map(|location| location.parse())
+            .transpose()
+    }
+
+    /// Archive and upload a build, and returns its fingerprint hash
+    pub fn push(&self, build: &Build, verbose: bool) -> Result<String> {
+        let fingerprint = build
+            .info()?
+            .and_then(|info| info.fingerprint)
+            .ok_or_else(|| Error::InvalidBuild {
+                name: build.name().into(),
+                message: "Fingerprint is not recorded, install the build again".into(),
+            })?;
+        fingerprint.check_sources()?;
+        let hash = fingerprint.hash();
+        // Next to the build, which has room for its archive
+        let parent = build.prefix().parent().expect("prefix of a build");
+        let staging = tempfile::Builder::new()
+            .prefix(".llvmmgmt-cache-")
+            .tempdir_in(parent)
+            .with(parent)?;
+        let name = artifact(&hash);
+        let archive = staging.path().join(&name);
+        archive::create(build, &archive, Compression::Zstd, verbose)?;
+        let checksum = staging.path().join(sidecar(&name));
+        fs::write(
+            &checksum,
+            format!("{}  {}\n", archive::file_checksum(&archive)?, name),
+        )
+        .with(&checksum)?;
+        // The checksum goes first, so that a partially uploaded archive fails the verification
+        self.put(&checksum, &sidecar(&name))?;
+        self.put(&archive, &name)?;
+        info!("Pushed {} to {} as {}", build.name(), self, name);
+        Ok(hash)
+    }
+
+    /// Fetch and import the build of the fingerprint hash, or `None` if it is not cached
+    pub fn pull(
+        &self,
+        hash: &str,
+        options: &ImportOptions,
+        verbose: bool,
+    ) -> Result<Option<Imported>> {
+        self.pull_into(hash, &data_dir()?, options, verbose)
+    }
+
+    fn pull_into(
+        &self,
+        hash: &str,
+        data_dir: &Path,
+        options: &ImportOptions,
+        verbose: bool,
+    ) -> Result<Option<Imported>> {
+        let staging = tempfile::Builder::new()
+            .prefix(".llvmmgmt-cache-")
+            .tempdir_in(data_dir)
+            .with(data_dir)?;
+        let name = artifact(hash);
+        let archive = staging.path().join(&name);
+        if !self.get(&sidecar(&name), &staging.path().join(sidecar(&name)))?
+            || !self.get(&name, &archive)?
+        {
+            info!("{} is not in the binary cache {}", hash, self);
+            return Ok(None);
+        }
+        let options = ImportOptions {
+            fingerprint: Some(hash.into()),
+            ..options.clone()
+        };
+        archive::import_into(&archive, data_dir, &options, verbose).map(Some)
+    }
+
+    /// Fetch the build of the entry if the cache has a build of the same fingerprint
+    pub fn fetch(&self, entry: &Entry, force: bool, verbose: bool) -> Result<Option<Imported>> {
+        let fingerprint = entry.fingerprint()?;
+        fingerprint.check_sources()?;
+        let hash = fingerprint.hash();
+        info!(
+            "Look up {} in the binary cache: {}",
+            entry.build_name(),
+            hash
+        );
+        let options = ImportOptions {
+            name: Some(entry.build_name()),
+            force,
+            ..Default::default()
+        };
+        self.pull(&hash, &options, verbose)
+    }
+
+    fn put(&self, src: &Path, name: &str) -> Result<()> {
+        match self {
+            BinaryCache::Dir(dir) => {
+                fs::create_dir_all(dir).with(dir)?;
+                // Renamed into place not to expose a partial file
+                let tmp = dir.join(format!(".{}.{}.tmp", name, process::id()));
+                fs::copy(src, &tmp).with(&tmp)?;
+                let dest = dir.join(name);
+                fs::rename(&tmp, &dest).with(&dest)?;
+            }
+            BinaryCache::Http(base) => {
+                let url = format!("{}/{}", base, name);
+                let file = fs::File::open(src).with(src)?;
+                let len = file.metadata().with(src)?.len();
+                let res = reqwest::blocking::Client::new()
+                    .put(&url)
+                    .body(reqwest::blocking::Body::sized(file, len))
+                    .send()?;
+                if !res.status().is_success() {
+                    return Err(Error::HttpError {
+                        url,
+                        status: res.status(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Download a file into `dest`, and returns false if it does not exist
+    fn get(&self, name: &str, dest: &Path) -> Result<bool> {
+        match self {
+            BinaryCache::Dir(dir) => {
+                let path = dir.join(name);
+                if !path.is_file() {
+                    return Ok(false);
+                }
+                fs::copy(&path, dest).with(&path)?;
+            }
+            BinaryCache::Http(base) => {
+                let url = format!("{}/{}", base, name);
+                let mut res = reqwest::blocking::get(&url)?;
+                if res.status() == reqwest::StatusCode::NOT_FOUND {
+                    return Ok(false);
+                }
+                if !res.status().is_success() {
+                    return Err(Error::HttpError {
+                        url,
+                        status: res.status(),
+                    });
+                }
+                let mut file = fs::File::create(dest).with(dest)?;
+                res.copy_to(&mut file)?;
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        build::BuildInfo,
+        entry::{CMakeGenerator, EntrySetting},
+        fingerprint::{Fingerprint, Source},
+        testing,
+    };
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    /// Build with a fingerprint, whose source is identified unless `revision` is `None`
+    fn fake_build(data_dir: &Path, name: &str, revision: Option<&str>) -> Result<Build> {
+        let build = testing::fake_build(data_dir, name)?;
+        let info = BuildInfo {
+            entry: name.into(),
+            fingerprint: Some(Fingerprint {
+                entry: name.into(),
+                variant: None,
+                sources: vec![Source {
+                    name: "llvm".into(),
+                    url: "https://github.com/llvm/llvm-project".into(),
+                    revision: revision.map(Into::into),
+                }],
+                setting: EntrySetting::default(),
+                generator: CMakeGenerator::Ninja,
+                host_triple: "x86_64-linux-gnu".into(),
+                compiler: Some("gcc (GCC) 13.2.0".into()),
+            }),
+            ..Default::default()
+        };
+        info.save(build.prefix())?;
+        Ok(build)
+    }
+
+    fn roundtrip(cache: &BinaryCache) -> Result<()> {
+        let src = tempfile::TempDir::new().with("/tmp")?;
+        let dest = tempfile::TempDir::new().with("/tmp")?;
+        let build = fake_build(
+            src.path(),
+            "18.1.8",
+            Some("3b5b5c1ec4a3095ab096dd780e84d7ab81f3d7ff"),
+        )?;
+        let hash = cache.push(&build, false)?;
+
+        let options = ImportOptions {
+            name: Some("llvm-18".into()),
+            ..Default::default()
+        };
+        let imported = cache
+            .pull_into(&hash, dest.path(), &options, false)?
+            .unwrap();
+        assert_eq!(imported.build.prefix(), dest.path().join("llvm-18"));
+        assert_eq!(
+            imported.build.info()?.unwrap().fingerprint.unwrap().hash(),
+            hash
+        );
+        assert_eq!(imported.relocation.unwrap().from, build.prefix());
+
+        // Not cached
+        assert!(cache
+            .pull_into(&"0".repeat(64), dest.path(), &options, false)?
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn dir_cache() -> Result<()> {
+        let dir = tempfile::TempDir::new().with("/tmp")?;
+        let cache: BinaryCache = dir.path().to_str().unwrap().parse()?;
+        assert_eq!(cache, BinaryCache::Dir(dir.path().into()));
+        roundtrip(&cache)?;
+
+        // Stored under a wrong fingerprint
+        let hash = fs::read_dir(dir.path())
+            .with(dir.path())?
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .find_map(|name| name.strip_suffix(".tar.zst").map(String::from))
+            .unwrap();
+        let wrong = "f".repeat(64);
+        for ext in ["tar.zst", "tar.zst.sha256"] {
+            fs::copy(
+                dir.path().join(format!("{}.{}", hash, ext)),
+                dir.path().join(format!("{}.{}", wrong, ext)),
+            )
+            .with(dir.path())?;
+        }
+        let dest = tempfile::TempDir::new().with("/tmp")?;
+        assert!(matches!(
+            cache.pull_into(&wrong, dest.path(), &Default::default(), false),
+            Err(Error::InvalidArchive { .. })
+        ));
+        assert!(!dest.path().join("18.1.8").exists());
+
+        // Not identified by the sources
+        let src = tempfile::TempDir::new().with("/tmp")?;
+        let local = fake_build(src.path(), "local", None)?;
+        assert!(matches!(
+            cache.push(&local, false),
+            Err(Error::InvalidEntry { .. })
+        ));
+        Ok(())
+    }
+
+    /// Stand-in of an HTTP binary cache storing files in memory
+    fn serve() -> String {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let port = server.server_addr().to_ip().unwrap().port();
+        let files = Arc::new(Mutex::new(HashMap::<String, Vec<u8>>::new()));
+        thread::spawn(move || {
+            for mut request in server.incoming_requests() {
+                let url = request.url().to_string();
+                let response = match request.method() {
+                    tiny_http::Method::Put => {
+                        let mut data = Vec::new();
+                        request.as_reader().read_to_end(&mut data).unwrap();
+                        files.lock().unwrap().insert(url, data);
+                        tiny_http::Response::from_data(Vec::new()).with_status_code(201)
+                    }
+                    tiny_http::Method::Get => match files.lock().unwrap().get(&url) {
+                        Some(data) => tiny_http::Response::from_data(data.clone()),
+                        None => tiny_http::Response::from_data(Vec::new()).with_status_code(404),
+                    },
+                    _ => tiny_http::Response::from_data(Vec::new()).with_status_code(405),
+                };
+                request.respond(response).unwrap();
+            }
+        });
+        format!("http://127.0.0.1:{}/llvmmgmt/", port)
+    }
+
+    #[test]
+    fn http_cache() -> Result<()> {
+        let url = serve();
+        let cache: BinaryCache = url.parse()?;
+        assert_eq!(cache, BinaryCache::Http(url.trim_end_matches('/').into()));
+        roundtrip(&cache)
+    }
+}
@@ -0,0 +1,370 @@
+//! Multi-stage bootstrap builds of clang
+//!
+//! ```toml
+//! [toolchain]
+//! url = "https://github.com/llvm/llvm-project/releases/download/llvmorg-18.1.8/llvm-18.1.8.src.tar.xz"
+//! # tools = [...] clang and lld are required
+//!
+//! [toolchain.bootstrap]
+//! pgo     = true  # optimize the final stage with profiles of an instrumented stage
+//! thinlto = true  # build the final stage with ThinLTO
+//! ```
+//!
+//! This uses `CLANG_ENABLE_BOOTSTRAP` of the LLVM build system. A stage-1 clang is built by the host compiler,
+//! and it builds the final stage-2 clang, which is the only stage installed into the prefix.
+//! With `pgo`, an instrumented stage-2 is built and trained by `generate-profdata` before the final stage:
+//!
+//! ```text
+//! stage1 -> stage2-instrumented -> profile training -> stage2 -> install
+//! ```
+//!
+//! When the source is a checkout of the LLVM monorepo, the upstream cache files in `clang/cmake/caches`
+//! (`PGO.cmake` and `PGO-stage2.cmake`) are used as a base, or in `tools/clang/cmake/caches` for official entries.
+
+use log::info;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cmake::{self, CacheValue},
+    entry::Entry,
+    error::*,
+};
+
+const INSTRUMENTED_CACHE: &str = "bootstrap-instrumented.cmake";
+
+/// Setting of multi-stage build, `bootstrap` table of an entry
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
+pub struct Bootstrap {
+    /// Build the final stage with profile-guided optimization
+    #[serde(default)]
+    pub pgo: bool,
+
+    /// Build the final stage with ThinLTO
+    #[serde(default)]
+    pub thinlto: bool,
+}
+
+/// A stage of build, e.g. stage2 of multi-stage build, which corresponds to `cmake --build` with targets
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stage {
+    pub name: &'static str,
+    pub targets: Vec<String>,
+}
+
+impl Stage {
+    pub fn new(name: &'static str, targets: &[&str]) -> Self {
+        Stage {
+            name,
+            targets: targets.iter().map(|target| target.to_string()).collect(),
+        }
+    }
+}
+
+impl Bootstrap {
+    /// Stages in build order
+    ///
+    /// ```
+    /// use llvmmgmt::bootstrap::Bootstrap;
+    /// let bootstrap = Bootstrap { pgo: true, thinlto: false };
+    /// let names: Vec<_> = bootstrap.stages().iter().map(|stage| stage.name).collect();
+    /// assert_eq!(names, ["stage1", "stage2-instrumented", "profile training", "stage2", "install"]);
+    /// ```
+    pub fn stages(&self) -> Vec<Stage> {
+        let mut stages = vec![if self.thinlto {
+            // stage1 lld links the final stage
+            Stage::new("stage1", &["clang", "lld"])
+        } else {
+            Stage::new("stage1", &["clang"])
+        }];
+        if self.pgo {
+            stages.push(Stage::new("stage2-instrumented", &["stage2-instrumented"]));
+            stages.push(Stage::new(
+                "profile training",
+                &["stage2-instrumented-generate-profdata"],
+            ));
+        }
+        stages.push(Stage::new("stage2", &["stage2"]));
+        stages.push(Stage::new("install", &["stage2-install"]));
+        stages
+    }
+
+    /// CMake options of stage1, which may write cache files for the later stages into the build directory
+    pub fn options(&self, entry: &Entry) -> Result<Vec<String>> {
+        let caches = upstream_caches(&entry.src_dir()?);
+        let mut opts = Vec::new();
+        if self.pgo {
+            if let Some(pgo) = caches.as_ref().map(|dir| dir.join("PGO.cmake")) {
+                info!("Use upstream cache file: {}", pgo.display());
+                opts.push("-C".into());
+                opts.push(pgo.display().to_string());
+            }
+        }
+        opts.push("-DCLANG_ENABLE_BOOTSTRAP=ON".into());
+
+        // Options for the final stage are passed to the stage just before it with BOOTSTRAP_ prefix
+        let mut final_opts = vec![(
+            "CMAKE_INSTALL_PREFIX".to_string(),
+            CacheValue::String(entry.prefix()?.display().to_string()),
+        )];
+        if self.thinlto {
+            final_opts.push(("LLVM_ENABLE_LTO".into(), "Thin".into()));
+            final_opts.push(("LLVM_ENABLE_LLD".into(), true.into()));
+        }
+        let target = &entry.setting().target;
+        if !target.is_empty() {
+            final_opts.push((
+                "LLVM_TARGETS_TO_BUILD".into(),
+                CacheValue::String(target.join(";")),
+            ));
+        }
+        for (k, v) in &entry.setting().option {
+            final_opts.push((k.clone(), v.clone()));
+        }
+
+        if self.pgo {
+            let cache = entry.build_dir()?.join(INSTRUMENTED_CACHE);
+            write_instrumented_cache(&cache, caches.as_deref(), &final_opts)?;
+            opts.push("-DBOOTSTRAP_LLVM_BUILD_INSTRUMENTED=IR".into());
+            opts.push("-DCLANG_BOOTSTRAP_TARGETS=generate-profdata;stage2;stage2-install".into());
+            opts.push(format!(
+                "-DCLANG_BOOTSTRAP_CMAKE_ARGS=-C;{}",
+                cache.display()
+            ));
+        } else {
+            opts.push("-DCLANG_BOOTSTRAP_TARGETS=install".into());
+            for (k, v) in &final_opts {
+                opts.push(cmake::define(&format!("BOOTSTRAP_{k}"), v));
+            }
+        }
+        Ok(opts)
+    }
+}
+
+/// `clang/cmake/caches` in the LLVM monorepo, where `src_dir` is its `llvm` directory,
+/// or in `tools/clang` where the clang tarball of an official entry is expanded
+fn upstream_caches(src_dir: &Path) -> Option<PathBuf> {
+    let monorepo = src_dir.parent().map(|root| root.join("clang/cmake/caches"));
+    let tools = src_dir.join("tools/clang/cmake/caches");
+    monorepo
+        .into_iter()
+        .chain([tools])
+        .find(|caches| caches.join("PGO.cmake").exists())
+}
+
+/// Cache file for the instrumented stage, which chains the final stage
+fn write_instrumented_cache(
+    path: &Path,
+    caches: Option<&Path>,
+    final_opts: &[(String, CacheValue)],
+) -> Result<()> {
+    let mut script = String::new();
+    if let Some(caches) = caches {
+        let stage2 = caches.join("PGO-stage2.cmake");
+        script += &format!("include(\"{}\")\n", quote(&stage2.display().to_string()));
+    }
+    script += &cache_entry("CLANG_ENABLE_BOOTSTRAP", &true.into());
+    script += &cache_entry("CLANG_BOOTSTRAP_TARGETS", &"install".into());
+    script += &cache_entry("CLANG_BOOTSTRAP_CMAKE_ARGS", &"".into());
+    for (k, v) in final_opts {
+        script += &cache_entry(&format!("BOOTSTRAP_{k}"), v);
+    }
+    fs::write(path, script).with(path)?;
+    Ok(())
+}
+
+fn cache_entry(key: &str, value: &CacheValue) -> String {
+    let cache_type = value.cache_type().map_or("STRING", |t| t.as_str());
+    format!(
+        "set({} \"{}\" CACHE {} \"\" FORCE)\n",
+        key,
+        quote(&value.value()),
+        cache_type
+    )
+}
+
+/// Escape a string for a quoted argument of CMake, which expands escape sequences and variable references
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | '"' | '$') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted
+}
+
+/// Wall time of each stage, reported after the build and recorded in [stats](../stats/index.html)
+#[derive(Debug, Default)]
+pub struct StageTimer {
+    finished: Vec<(&'static str, Duration)>,
+}
+
+impl StageTimer {
+    /// Run a stage or phase and record its wall time
+    pub fn run(&mut self, name: &'static str, f: impl FnOnce() -> Result<()>) -> Result<()> {
+        info!("Start {}", name);
+        let start = Instant::now();
+        f()?;
+        let elapsed = start.elapsed();
+        info!("Finished {} in {}", name, format_duration(elapsed));
+        self.finished.push((name, elapsed));
+        Ok(())
+    }
+
+    pub fn finished(&self) -> &[(&'static str, Duration)] {
+        &self.finished
+    }
+}
+
+/// Summary of the stages printed after the build
+///
+/// ```
+/// use llvmmgmt::bootstrap::StageTimer;
+/// let mut timer = StageTimer::default();
+/// timer.run("configure", || Ok(())).unwrap();
+/// assert_eq!(timer.to_string(), "Build time:\n           configure: 0s\n               total: 0s\n");
+/// ```
+impl fmt::Display for StageTimer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Build time:")?;
+        for (name, elapsed) in &self.finished {
+            writeln!(f, "{:>20}: {}", name, format_duration(*elapsed))?;
+        }
+        let total = self.finished.iter().map(|(_, elapsed)| *elapsed).sum();
+        writeln!(f, "{:>20}: {}", "total", format_duration(total))
+    }
+}
+
+/// Format duration as `1h02m03s`
+///
+/// ```
+/// use llvmmgmt::bootstrap::format_duration;
+/// use std::time::Duration;
+/// assert_eq!(format_duration(Duration::from_secs(3723)), "1h02m03s");
+/// assert_eq!(format_duration(Duration::from_secs(63)), "1m03s");
+/// assert_eq!(format_duration(Duration::from_secs(3)), "3s");
+/// ```
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 {
+        format!("{h}h{m:02}m{s:02}s")
+    } else if m > 0 {
+        format!("{m}m{s:02}s")
+    } else {
+        format!("{s}s")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::EntrySetting;
+
+    #[test]
+    fn pgo_options() -> Result<()> {
+        let root = tempfile::TempDir::new().with("/tmp")?;
+        let src = root.path().join("llvm");
+        let caches = root.path().join("clang/cmake/caches");
+        fs::create_dir_all(&src).with(&src)?;
+        fs::create_dir_all(&caches).with(&caches)?;
+        fs::write(caches.join("PGO.cmake"), "").with(&caches)?;
+
+        let bootstrap = Bootstrap {
+            pgo: true,
+            thinlto: false,
+        };
+        let mut setting = EntrySetting {
+            path: Some(src.display().to_string()),
+            bootstrap: Some(bootstrap.clone()),
+            target: vec!["X86".into(), "AArch64".into()],
+            ..Default::default()
+        };
+        setting.option.insert(
+            "CLANG_VENDOR".into(),
+            CacheValue::String(r#"My "LLVM" ${USER}\"#.into()),
+        );
+        let entry = Entry::parse_setting("pgo", None, setting)?;
+
+        let names: Vec<_> = bootstrap.stages().iter().map(|stage| stage.name).collect();
+        assert_eq!(
+            names,
+            [
+                "stage1",
+                "stage2-instrumented",
+                "profile training",
+                "stage2",
+                "install"
+            ]
+        );
+        let targets: Vec<_> = bootstrap
+            .stages()
+            .into_iter()
+            .flat_map(|stage| stage.targets)
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                "clang",
+                "stage2-instrumented",
+                "stage2-instrumented-generate-profdata",
+                "stage2",
+                "stage2-install"
+            ]
+        );
+
+        let cache = entry.build_dir()?.join(INSTRUMENTED_CACHE);
+        let opts = bootstrap.options(&entry)?;
+        assert_eq!(
+            opts,
+            [
+                "-C".to_string(),
+                caches.join("PGO.cmake").display().to_string(),
+                "-DCLANG_ENABLE_BOOTSTRAP=ON".into(),
+                "-DBOOTSTRAP_LLVM_BUILD_INSTRUMENTED=IR".into(),
+                "-DCLANG_BOOTSTRAP_TARGETS=generate-profdata;stage2;stage2-install".into(),
+                format!("-DCLANG_BOOTSTRAP_CMAKE_ARGS=-C;{}", cache.display()),
+            ]
+        );
+
+        // The instrumented stage builds the final stage with the options of the entry
+        let script = fs::read_to_string(&cache).with(&cache)?;
+        let lines: Vec<_> = script.lines().collect();
+        assert_eq!(
+            lines[0],
+            format!("include(\"{}\")", caches.join("PGO-stage2.cmake").display())
+        );
+        assert!(lines.contains(&"set(CLANG_BOOTSTRAP_TARGETS \"install\" CACHE STRING \"\" FORCE)"));
+        assert!(lines.contains(
+            &format!(
+                "set(BOOTSTRAP_CMAKE_INSTALL_PREFIX \"{}\" CACHE STRING \"\" FORCE)",
+                entry.prefix()?.display()
+            )
+            .as_str()
+        ));
+        assert!(lines.contains(
+            &r#"set(BOOTSTRAP_CLANG_VENDOR "My \"LLVM\" \${USER}\\" CACHE STRING "" FORCE)"#
+        ));
+        assert!(lines.contains(
+            &r#"set(BOOTSTRAP_LLVM_TARGETS_TO_BUILD "X86;AArch64" CACHE STRING "" FORCE)"#
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn upstream_caches_of_tarballs() -> Result<()> {
+        let src = tempfile::TempDir::new().with("/tmp")?;
+        assert_eq!(upstream_caches(src.path()), None);
+        let caches = src.path().join("tools/clang/cmake/caches");
+        fs::create_dir_all(&caches).with(&caches)?;
+        fs::write(caches.join("PGO.cmake"), "").with(&caches)?;
+        assert_eq!(upstream_caches(src.path()), Some(caches));
+        Ok(())
+    }
+}
@@ -2,18 +2,124 @@
 
 use glob::glob;
 use log::*;
+use serde_derive::{Deserialize, Serialize};
 
 use std::{
+    collections::BTreeMap,
     env, fs,
-    io::{self, Read, Write},
+    io::{Read, Write},
     path::{Path, PathBuf},
-    process::Command,
 };
 
 use crate::config::*;
 use crate::error::*;
+use crate::{
+    archive::{self, Compression, ImportOptions, Imported},
+    entry::{self, Entry},
+    fingerprint::Fingerprint,
+    jobs::{Jobs, Retry},
+    stats::BuildStats,
+    toolchain::{self, Launcher, Linker},
+};
 
 const LLVMMGMT_FN: &str = ".llvmmgmt";
+const BUILD_INFO_FN: &str = ".llvmmgmt-build.toml";
+
+/// How a build has been produced, recorded in its prefix by `llvmmgmt install` and `llvmmgmt entry build`
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
+pub struct BuildInfo {
+    /// Name of the entry
+    pub entry: String,
+
+    /// Variant applied to the entry
+    pub variant: Option<String>,
+
+    /// Profiles applied to the entry
+    #[serde(default)]
+    pub profiles: Vec<String>,
+
+    /// Installed distribution components, empty if everything is installed
+    #[serde(default)]
+    pub components: Vec<String>,
+
+    /// Targets built by `entry build --targets`, empty for a full build
+    #[serde(default)]
+    pub targets: Vec<String>,
+
+    /// Host triple of a cross compiled build
+    pub host_triple: Option<String>,
+
+    #[serde(default)]
+    pub launcher: Launcher,
+
+    #[serde(default)]
+    pub linker: Linker,
+
+    /// Jobs reduced after OOM-like failures, which limit the next build of the entry
+    pub safe_jobs: Option<Jobs>,
+
+    /// Retries with fewer jobs in the last build
+    #[serde(default)]
+    pub retries: Vec<Retry>,
+
+    /// Timing and statistics of the last build
+    pub stats: Option<BuildStats>,
+
+    /// Effective environment of the last build, see [environment](../environment/index.html) module
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+
+    /// Fingerprint of the entry, which identifies the build in the binary cache.
+    /// See [fingerprint](../fingerprint/index.html) module
+    pub fingerprint: Option<Fingerprint>,
+}
+
+impl BuildInfo {
+    pub fn load(prefix: &Path) -> Result<Option<Self>> {
+        let path = prefix.join(BUILD_INFO_FN);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(toml::from_str(
+            &fs::read_to_string(&path).with(&path)?,
+        )?))
+    }
+
+    /// Whether the build runs on the current host, i.e. not cross compiled for another host
+    pub fn is_runnable(&self) -> bool {
+        match &self.host_triple {
+            Some(triple) => toolchain::runs_on_host(triple),
+            None => true,
+        }
+    }
+
+    /// Entry of the build with the recorded variant and profiles
+    pub fn load_entry(&self) -> Result<Entry> {
+        entry::load_entry_with(&self.entry, self.variant.as_deref(), &self.profiles)
+    }
+
+    /// Whether only some targets are installed by `entry build --targets`
+    pub fn is_partial(&self) -> bool {
+        !self.targets.is_empty()
+    }
+
+    /// Remove the information, e.g. of a failed build in the build directory
+    pub fn remove(prefix: &Path) -> Result<()> {
+        let path = prefix.join(BUILD_INFO_FN);
+        if path.exists() {
+            fs::remove_file(&path).with(&path)?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, prefix: &Path) -> Result<()> {
+        let path = prefix.join(BUILD_INFO_FN);
+        // Through `toml::Value`, which writes plain values before tables regardless of the field order
+        let value = toml::Value::try_from(self)?;
+        fs::write(&path, toml::to_string(&value)?).with(&path)?;
+        Ok(())
+    }
+}
 
 #[derive(Debug)]
 pub struct Build {
@@ -63,6 +169,11 @@ impl Build {
         &self.prefix
     }
 
+    /// Recorded information of the build, `None` for the system build or builds made by old llvmmgmt
+    pub fn info(&self) -> Result<Option<BuildInfo>> {
+        BuildInfo::load(&self.prefix)
+    }
+
     pub fn env_path(&self) -> Option<&Path> {
         match self.llvmmgmt {
             Some(ref path) => Some(path.as_path()),
@@ -82,16 +193,9 @@ impl Build {
         Ok(())
     }
 
-    pub fn archive(&self, verbose: bool) -> Result<()> {
-        let filename = format!("{}.tar.xz", self.name);
-        Command::new("tar")
-            .arg(if verbose { "cvf" } else { "cf" })
-            .arg(&filename)
-            .arg("--use-compress-prog=pixz")
-            .arg(&self.name)
-            .current_dir(data_dir()?)
-            .check_run()?;
-        println!("{}", data_dir()?.join(filename).display());
+    /// Archive into `output`, see [archive](../archive/index.html) module
+    pub fn archive(&self, output: &Path, compression: Compression, verbose: bool) -> Result<()> {
+        archive::create(self, output, compression, verbose)?;
         Ok(())
     }
 
@@ -169,18 +273,55 @@ pub fn seek_build() -> Result<Build> {
     Ok(Build::system())
 }
 
-pub fn expand(archive: &Path, verbose: bool) -> Result<()> {
-    if !archive.exists() {
-        return Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            "Archive doest not found",
-        ))
-        .with(archive);
-    }
-    Command::new("tar")
-        .arg(if verbose { "xvf" } else { "xf" })
-        .arg(archive)
-        .current_dir(data_dir()?)
-        .check_run()?;
-    Ok(())
-}
\ No newline at end of file
+/// Import an archived build, see [archive](../archive/index.html) module
+pub fn expand(archive: &Path, options: &ImportOptions, verbose: bool) -> Result<Imported> {
+    archive::import(archive, options, verbose)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn build_info_roundtrip() -> Result<()> {
+        let prefix = TempDir::new().with("/tmp")?;
+        let jobs = Jobs {
+            compile: 4,
+            link: 1,
+        };
+        let info = BuildInfo {
+            entry: "18.1.8".into(),
+            variant: Some("asserts".into()),
+            safe_jobs: Some(jobs),
+            retries: vec![Retry {
+                target: "install".into(),
+                reason: "ld terminated with signal 9 [Killed]".into(),
+                jobs,
+            }],
+            ..Default::default()
+        };
+        info.save(prefix.path())?;
+        assert_eq!(BuildInfo::load(prefix.path())?, Some(info));
+        Ok(())
+    }
+
+    #[test]
+    fn load_entry_as_installed() -> Result<()> {
+        let profiles = vec!["dist".to_string()];
+        let info = BuildInfo {
+            entry: "18.1.8".into(),
+            variant: Some("debug".into()),
+            profiles: profiles.clone(),
+            ..Default::default()
+        };
+        // The variant is layered on the profiles as `entry build --profile dist --variant debug`
+        let entry = info.load_entry()?;
+        assert_eq!(entry.setting().build_type, entry::BuildType::Debug);
+        assert_eq!(
+            entry.setting(),
+            entry::load_entry_with("18.1.8", Some("debug"), &profiles)?.setting()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+//! Typed values of CMake cache options
+//!
+//! `option` table of an entry and a variant accepts TOML values, which are converted into the CMake syntax:
+//!
+//! ```toml
+//! [my-llvm.option]
+//! LLVM_ENABLE_ASSERTIONS = true                      # -DLLVM_ENABLE_ASSERTIONS=ON
+//! LLVM_PARALLEL_TABLEGEN_JOBS = 4                    # -DLLVM_PARALLEL_TABLEGEN_JOBS=4
+//! LLVM_ENABLE_PROJECTS = ["clang", "lld"]            # -DLLVM_ENABLE_PROJECTS=clang;lld
+//! LLVM_BINUTILS_INCDIR = { value = "/usr/include", type = "PATH" }  # -DLLVM_BINUTILS_INCDIR:PATH=/usr/include
+//! ```
+//!
+//! Options are passed to cmake in the order of their names.
+
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Options set by `-D` in the order of their names
+pub type CacheOptions = BTreeMap<String, CacheValue>;
+
+/// Type of a CMake cache entry
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum CacheType {
+    Bool,
+    Filepath,
+    Path,
+    String,
+    Internal,
+}
+
+impl CacheType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheType::Bool => "BOOL",
+            CacheType::Filepath => "FILEPATH",
+            CacheType::Path => "PATH",
+            CacheType::String => "STRING",
+            CacheType::Internal => "INTERNAL",
+        }
+    }
+}
+
+/// Value of a CMake cache option written in TOML
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum CacheValue {
+    /// `ON` or `OFF`
+    Bool(bool),
+    Integer(i64),
+    String(String),
+    /// `;`-separated list
+    List(Vec<String>),
+    /// Value with an explicit cache type
+    Typed {
+        value: Box<CacheValue>,
+        #[serde(rename = "type")]
+        cache_type: CacheType,
+    },
+}
+
+impl From<&str> for CacheValue {
+    fn from(value: &str) -> Self {
+        CacheValue::String(value.into())
+    }
+}
+
+impl From<bool> for CacheValue {
+    fn from(value: bool) -> Self {
+        CacheValue::Bool(value)
+    }
+}
+
+impl CacheValue {
+    /// Value in CMake syntax
+    ///
+    /// ```
+    /// use llvmmgmt::cmake::CacheValue;
+    /// assert_eq!(CacheValue::Bool(true).value(), "ON");
+    /// assert_eq!(CacheValue::List(vec!["X86".into(), "AArch64".into()]).value(), "X86;AArch64");
+    /// ```
+    pub fn value(&self) -> String {
+        match self {
+            CacheValue::Bool(true) => "ON".into(),
+            CacheValue::Bool(false) => "OFF".into(),
+            CacheValue::Integer(n) => n.to_string(),
+            CacheValue::String(s) => s.clone(),
+            CacheValue::List(list) => list.join(";"),
+            CacheValue::Typed { value, .. } => value.value(),
+        }
+    }
+
+    /// Explicit cache type
+    pub fn cache_type(&self) -> Option<CacheType> {
+        match self {
+            CacheValue::Typed { cache_type, .. } => Some(*cache_type),
+            _ => None,
+        }
+    }
+}
+
+/// `-D` option for cmake
+///
+/// ```
+/// use llvmmgmt::cmake::{define, CacheType, CacheValue};
+/// assert_eq!(define("LLVM_ENABLE_ASSERTIONS", &CacheValue::Bool(false)), "-DLLVM_ENABLE_ASSERTIONS=OFF");
+/// let path = CacheValue::Typed { value: Box::new("/usr/include".into()), cache_type: CacheType::Path };
+/// assert_eq!(define("LLVM_BINUTILS_INCDIR", &path), "-DLLVM_BINUTILS_INCDIR:PATH=/usr/include");
+/// ```
+pub fn define(key: &str, value: &CacheValue) -> String {
+    match value.cache_type() {
+        Some(cache_type) => format!("-D{}:{}={}", key, cache_type.as_str(), value.value()),
+        None => format!("-D{}={}", key, value.value()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed_options() -> Result<(), toml::de::Error> {
+        let options: CacheOptions = toml::from_str(
+            r#"
+            LLVM_TARGETS_TO_BUILD = ["X86", "AArch64"]
+            LLVM_ENABLE_ASSERTIONS = true
+            LLVM_PARALLEL_TABLEGEN_JOBS = 4
+            CMAKE_C_FLAGS = "-march=native"
+            LLVM_BINUTILS_INCDIR = { value = "/usr/include", type = "PATH" }
+            "#,
+        )?;
+        let defines: Vec<_> = options.iter().map(|(k, v)| define(k, v)).collect();
+        assert_eq!(
+            defines,
+            [
+                "-DCMAKE_C_FLAGS=-march=native",
+                "-DLLVM_BINUTILS_INCDIR:PATH=/usr/include",
+                "-DLLVM_ENABLE_ASSERTIONS=ON",
+                "-DLLVM_PARALLEL_TABLEGEN_JOBS=4",
+                "-DLLVM_TARGETS_TO_BUILD=X86;AArch64",
+            ]
+        );
+        assert!(toml::from_str::<CacheOptions>("A = { value = 1, type = \"NUMBER\" }").is_err());
+        Ok(())
+    }
+}
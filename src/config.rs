@@ -1,17 +1,49 @@
 use log::info;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::io::Write;
 use std::path::PathBuf;
+use std::sync::OnceLock;
 
-use crate::error::*;
+use crate::{
+    error::*,
+    priority::Priority,
+    profile::Profile,
+    toolchain::{Launcher, Linker},
+};
 
 pub const APP_NAME: &str = "llvmmgmt";
 pub const ENTRY_TOML: &str = "entry.toml";
+pub const CONFIG_TOML: &str = "config.toml";
 
 const LLVM_MIRROR: &str = include_str!("llvm-mirror.toml");
 
+/// Base directories of the user, replaced by a temporary directory in unit tests
+#[cfg(not(test))]
+mod base {
+    pub use dirs::{cache_dir, config_dir, data_dir};
+}
+
+#[cfg(test)]
+mod base {
+    use std::path::PathBuf;
+
+    pub fn config_dir() -> Option<PathBuf> {
+        Some(crate::testing::home().join("config"))
+    }
+
+    pub fn cache_dir() -> Option<PathBuf> {
+        Some(crate::testing::home().join("cache"))
+    }
+
+    pub fn data_dir() -> Option<PathBuf> {
+        Some(crate::testing::home().join("data"))
+    }
+}
+
 pub fn config_dir() -> Result<PathBuf> {
-    let path = dirs::config_dir()
+    let path = base::config_dir()
         .ok_or(Error::UnsupportedOS)?
         .join(APP_NAME);
     if !path.exists() {
@@ -21,7 +53,7 @@ pub fn config_dir() -> Result<PathBuf> {
 }
 
 pub fn cache_dir() -> Result<PathBuf> {
-    let path = dirs::cache_dir()
+    let path = base::cache_dir()
         .ok_or(Error::UnsupportedOS)?
         .join(APP_NAME);
     if !path.exists() {
@@ -31,7 +63,7 @@ pub fn cache_dir() -> Result<PathBuf> {
 }
 
 pub fn data_dir() -> Result<PathBuf> {
-    let path = dirs::data_dir().ok_or(Error::UnsupportedOS)?.join(APP_NAME);
+    let path = base::data_dir().ok_or(Error::UnsupportedOS)?.join(APP_NAME);
     if !path.exists() {
         fs::create_dir_all(&path).with(&path)?;
     }
@@ -51,3 +83,74 @@ pub fn init_config() -> Result<()> {
         Err(Error::ConfigureAlreadyExists { path: entry })
     }
 }
+
+/// Expand `~` and environment variables in a path written in setting files
+pub fn expand_path(path: &str) -> PathBuf {
+    PathBuf::from(shellexpand::full(path).unwrap().to_string())
+}
+
+/// Global setting for all entries, decoded from `$XDG_CONFIG_HOME/llvmmgmt/config.toml`
+///
+/// ```toml
+/// build_root = "/scratch/llvmmgmt"
+/// build_type_subdir = true
+/// ```
+///
+/// Every field is optional, and settings in `entry.toml` take precedence.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
+pub struct Config {
+    /// Root of out-of-tree build directories. Entries are built in `{build_root}/{entry name}`.
+    pub build_root: Option<String>,
+
+    /// Separate build directories for each build type, e.g. `{build dir}/Debug`
+    #[serde(default)]
+    pub build_type_subdir: bool,
+
+    /// Default compiler launcher, see [toolchain](../toolchain/index.html) module
+    pub launcher: Option<Launcher>,
+
+    /// Default linker, see [toolchain](../toolchain/index.html) module
+    pub linker: Option<Linker>,
+
+    /// Default number of compile jobs, see [jobs](../jobs/index.html) module
+    pub jobs: Option<usize>,
+
+    /// Default number of link jobs
+    pub link_jobs: Option<usize>,
+
+    /// Default priority and resource limits of builds, see [priority](../priority/index.html) module
+    #[serde(default)]
+    pub priority: Priority,
+
+    /// User-defined build variants, see [variant](../variant/index.html) module
+    #[serde(default)]
+    pub variant: HashMap<String, Profile>,
+
+    /// User-defined build profiles, see [profile](../profile/index.html) module
+    #[serde(default)]
+    pub profile: HashMap<String, Profile>,
+
+    /// Directory or HTTP URL of the shared binary cache, see [binary_cache](../binary_cache/index.html) module
+    pub binary_cache: Option<String>,
+}
+
+impl Config {
+    /// Load `config.toml`, or the default setting if it does not exist
+    pub fn load() -> Result<Self> {
+        let path = config_dir()?.join(CONFIG_TOML);
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        Ok(toml::from_str(&fs::read_to_string(&path).with(&path)?)?)
+    }
+
+    /// `config.toml` loaded once for the process
+    pub fn global() -> Result<&'static Self> {
+        static CONFIG: OnceLock<Config> = OnceLock::new();
+        if let Some(config) = CONFIG.get() {
+            return Ok(config);
+        }
+        let config = Config::load()?;
+        Ok(CONFIG.get_or_init(|| config))
+    }
+}
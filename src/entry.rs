@@ -43,6 +43,47 @@
 //! Entry is regarded as *local* if there is `path` property, and *remote* if there is `url` property.
 //! Other options are common to *remote* entries.
 //!
+//! Build directory
+//! ----------------
+//! Entries are built in `build` under the source directory by default.
+//! `build_dir` changes it, and `build_type_subdir` separates the CMake cache of each build type:
+//!
+//! ```toml
+//! [my-local-llvm]
+//! path = "/path/to/your/src"
+//! build_dir = "/scratch/my-local-llvm"  # relative path is resolved from the source directory
+//! build_type_subdir = true              # Debug build in /scratch/my-local-llvm/Debug
+//! ```
+//!
+//! The default for all entries can be set by `build_root` and `build_type_subdir`
+//! in `$XDG_CONFIG_HOME/llvmmgmt/config.toml`, see [Config](../config/struct.Config.html).
+//!
+//! Build options
+//! --------------
+//! `option` table sets CMake cache options. Booleans, integers, and arrays are converted into the CMake syntax:
+//!
+//! ```toml
+//! [my-local-llvm.option]
+//! LLVM_ENABLE_ASSERTIONS = true              # ON
+//! LLVM_ENABLE_PROJECTS = ["clang", "lld"]    # clang;lld
+//! ```
+//!
+//! See [cmake](../cmake/index.html) module for explicit cache types.
+//!
+//! Installed files
+//! ----------------
+//! `install_components` installs only the listed distribution components,
+//! and `split_debug_info` moves debug info into `.debug` directories next to the binaries.
+//! See [install](../install/index.html) module.
+//! `build_targets`, usually given by `llvmmgmt entry build --targets`, builds and installs only the listed targets,
+//! and the build is recorded as partial. Targets of partial builds into the same prefix accumulate,
+//! and a partial build into the prefix of a full build is refused unless `--force` is given.
+//!
+//! Multi-stage builds
+//! -------------------
+//! `bootstrap` table builds a stage-2 clang by a stage-1 clang, optionally with PGO and ThinLTO.
+//! See [bootstrap](../bootstrap/index.html) module.
+//!
 //! Pre-defined entries
 //! ------------------
 //!
@@ -69,82 +110,242 @@ use itertools::*;
 use log::{info, warn};
 use semver::{Version, VersionReq};
 use serde_derive::{Deserialize, Serialize};
-use std::{collections::HashMap, fs, path::PathBuf, process, str::FromStr};
-
-use crate::{config::*, error::*, resource::*};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+};
+
+use crate::{
+    bootstrap::{format_duration, Bootstrap, Stage, StageTimer},
+    build::BuildInfo,
+    cmake::{self, CacheOptions},
+    config::*,
+    environment::BuildEnv,
+    error::*,
+    fingerprint::{Fingerprint, Source},
+    hook::{self, HookPoint, Hooks},
+    install,
+    jobs::{self, Jobs, Retry},
+    lit::{self, TestReport},
+    priority::{Limits, Priority},
+    profile,
+    progress::{self, Progress},
+    resource::*,
+    stats::{self, BuildStats},
+    toolchain::{Compiler, Cross, Launcher, Linker},
+    variant,
+};
 
 /// Option for CMake Generators
 ///
 /// - Official document: [CMake Generators](https://cmake.org/cmake/help/latest/manual/cmake-generators.7.html)
 ///
+/// Any generator listed in `cmake --help` is accepted in addition to the short names.
+/// Other names are kept as written, and checked by [validate](#method.validate) at configure:
+///
 /// ```
 /// use llvmmgmt::entry::CMakeGenerator;
 /// use std::str::FromStr;
 /// assert_eq!(CMakeGenerator::from_str("Makefile").unwrap(), CMakeGenerator::Makefile);
+/// assert_eq!(CMakeGenerator::from_str("Unix Makefiles").unwrap(), CMakeGenerator::Makefile);
 /// assert_eq!(CMakeGenerator::from_str("Ninja").unwrap(), CMakeGenerator::Ninja);
+/// assert_eq!(CMakeGenerator::from_str("Ninja Multi-Config").unwrap(), CMakeGenerator::NinjaMultiConfig);
 /// assert_eq!(CMakeGenerator::from_str("vs").unwrap(), CMakeGenerator::VisualStudio);
 /// assert_eq!(CMakeGenerator::from_str("VisualStudio").unwrap(), CMakeGenerator::VisualStudio);
-/// assert!(CMakeGenerator::from_str("MySuperBuilder").is_err());
+/// assert_eq!(CMakeGenerator::from_str("VisualStudioWin64").unwrap(), CMakeGenerator::VisualStudioWin64);
+/// assert_eq!(CMakeGenerator::from_str("Platform").unwrap(), CMakeGenerator::Platform);
+/// assert_eq!(CMakeGenerator::from_str("Xcode").unwrap(), CMakeGenerator::Other("Xcode".into()));
 /// ```
-#[derive(Deserialize, Serialize, PartialEq, Debug, Clone, Default)]
+///
+/// When an entry does not set `generator`, Ninja is used if it is installed, and Makefile otherwise.
+#[derive(Deserialize, Serialize, PartialEq, Debug, Clone)]
+#[serde(try_from = "String", into = "String")]
 pub enum CMakeGenerator {
-    #[default]
     /// Use platform default generator (without -G option)
     Platform,
     /// Unix Makefile
     Makefile,
     /// Ninja generator
     Ninja,
+    /// Ninja Multi-Config generator, which builds the configuration of `build_type`
+    NinjaMultiConfig,
     /// Visual Studio 15 2017
     VisualStudio,
     /// Visual Studio 15 2017 Win64
     VisualStudioWin64,
+    /// Other generator listed in `cmake --help`
+    Other(String),
 }
 
 impl FromStr for CMakeGenerator {
     type Err = Error;
     fn from_str(generator: &str) -> Result<Self> {
-        Ok(match generator.to_ascii_lowercase().as_str() {
-            "makefile" => CMakeGenerator::Makefile,
+        let normalized: String = generator
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '-' | '_'))
+            .collect::<String>()
+            .to_ascii_lowercase();
+        Ok(match normalized.as_str() {
+            "platform" => CMakeGenerator::Platform,
+            "makefile" | "unixmakefiles" => CMakeGenerator::Makefile,
             "ninja" => CMakeGenerator::Ninja,
+            "ninjamulticonfig" => CMakeGenerator::NinjaMultiConfig,
             "visualstudio" | "vs" => CMakeGenerator::VisualStudio,
-            _ => {
-                return Err(Error::UnsupportedGenerator {
-                    generator: generator.into(),
-                });
-            }
+            "visualstudiowin64" => CMakeGenerator::VisualStudioWin64,
+            _ => CMakeGenerator::Other(generator.trim().into()),
         })
     }
 }
 
+impl TryFrom<String> for CMakeGenerator {
+    type Error = Error;
+    fn try_from(generator: String) -> Result<Self> {
+        CMakeGenerator::from_str(&generator)
+    }
+}
+
+impl From<CMakeGenerator> for String {
+    fn from(generator: CMakeGenerator) -> Self {
+        match generator {
+            CMakeGenerator::Platform => "Platform".into(),
+            CMakeGenerator::Makefile => "Makefile".into(),
+            CMakeGenerator::Ninja => "Ninja".into(),
+            CMakeGenerator::NinjaMultiConfig => "Ninja Multi-Config".into(),
+            CMakeGenerator::VisualStudio => "VisualStudio".into(),
+            CMakeGenerator::VisualStudioWin64 => "VisualStudioWin64".into(),
+            CMakeGenerator::Other(name) => name,
+        }
+    }
+}
+
+/// Generators listed in `cmake --help`
+pub fn available_generators() -> Result<Vec<String>> {
+    let (help, _) = process::Command::new("cmake")
+        .arg("--help")
+        .check_output()?;
+    Ok(parse_generators(&help))
+}
+
+/// `CMAKE_GENERATOR` recorded in `CMakeCache.txt`
+fn cached_generator(cache: &str) -> Option<String> {
+    cache
+        .lines()
+        .find_map(|line| line.strip_prefix("CMAKE_GENERATOR:INTERNAL="))
+        .map(Into::into)
+}
+
+fn parse_generators(help: &str) -> Vec<String> {
+    help.lines()
+        .skip_while(|line| !line.starts_with("Generators"))
+        // Names are indented by two spaces or marked as default by `* `,
+        // and descriptions continued from the previous lines are indented deeper
+        .filter(|line| {
+            (line.starts_with("  ") && !line.starts_with("   ")) || line.starts_with("* ")
+        })
+        .filter_map(|line| {
+            let name = line[2..].split('=').next()?.trim();
+            if name.is_empty() {
+                None
+            } else {
+                Some(name.to_string())
+            }
+        })
+        .collect()
+}
+
 impl CMakeGenerator {
+    /// Ninja if it is installed in `PATH` of the build, and the platform default otherwise
+    pub fn detect(env: &BuildEnv) -> Self {
+        if env.which("ninja").is_some() || env.which("ninja-build").is_some() {
+            CMakeGenerator::Ninja
+        } else if cfg!(windows) {
+            CMakeGenerator::Platform
+        } else {
+            CMakeGenerator::Makefile
+        }
+    }
+
+    /// Check that a generator other than the short names is listed in `cmake --help`,
+    /// and returns it spelled as listed
+    pub fn validate(&self) -> Result<Self> {
+        let name = match self {
+            CMakeGenerator::Other(name) => name,
+            _ => return Ok(self.clone()),
+        };
+        let unsupported = || Error::UnsupportedGenerator {
+            generator: name.clone(),
+        };
+        let available = available_generators().map_err(|_| unsupported())?;
+        available
+            .into_iter()
+            .find(|available| available.eq_ignore_ascii_case(name))
+            .map(CMakeGenerator::Other)
+            .ok_or_else(unsupported)
+    }
+
+    /// Generator name for `-G` option
+    fn name(&self) -> Option<&str> {
+        match self {
+            CMakeGenerator::Platform => None,
+            CMakeGenerator::Makefile => Some("Unix Makefiles"),
+            CMakeGenerator::Ninja => Some("Ninja"),
+            CMakeGenerator::NinjaMultiConfig => Some("Ninja Multi-Config"),
+            CMakeGenerator::VisualStudio => Some("Visual Studio 15 2017"),
+            CMakeGenerator::VisualStudioWin64 => Some("Visual Studio 15 2017 Win64"),
+            CMakeGenerator::Other(name) => Some(name),
+        }
+    }
+
+    /// Uses Ninja as the build tool, where jobs pools are available
+    pub fn is_ninja(&self) -> bool {
+        self.name().is_some_and(|name| name.contains("Ninja"))
+    }
+
+    /// Generates build files for multiple configurations, and selects one at build time
+    pub fn is_multi_config(&self) -> bool {
+        self.name().is_some_and(|name| {
+            name.contains("Multi-Config") || name.starts_with("Visual Studio") || name == "Xcode"
+        })
+    }
+
     /// Option for cmake
     pub fn option(&self) -> Vec<String> {
-        match self {
-            CMakeGenerator::Platform => Vec::new(),
-            CMakeGenerator::Makefile => vec!["-G", "Unix Makefiles"],
-            CMakeGenerator::Ninja => vec!["-G", "Ninja"],
-            CMakeGenerator::VisualStudio => vec!["-G", "Visual Studio 15 2017"],
-            CMakeGenerator::VisualStudioWin64 => {
-                vec!["-G", "Visual Studio 15 2017 Win64", "-Thost=x64"]
-            }
+        let mut opts = Vec::new();
+        if let Some(name) = self.name() {
+            opts.push("-G".to_string());
+            opts.push(name.to_string());
+        }
+        if *self == CMakeGenerator::VisualStudioWin64 {
+            opts.push("-Thost=x64".into());
+        }
+        opts
+    }
+
+    /// Target to build everything without install
+    pub fn all_target(&self) -> &'static str {
+        match self.name() {
+            Some(name) if name.starts_with("Visual Studio") || name == "Xcode" => "ALL_BUILD",
+            None if cfg!(windows) => "ALL_BUILD",
+            _ => "all",
         }
-        .into_iter()
-        .map(|s| s.into())
-        .collect()
     }
 
     /// Option for cmake build mode (`cmake --build` command)
     pub fn build_option(&self, nproc: usize, build_type: BuildType) -> Vec<String> {
-        match self {
-            CMakeGenerator::VisualStudioWin64 | CMakeGenerator::VisualStudio => {
-                vec!["--config".into(), format!("{:?}", build_type)]
-            }
-            CMakeGenerator::Platform => Vec::new(),
-            CMakeGenerator::Makefile | CMakeGenerator::Ninja => {
-                vec!["--".into(), "-j".into(), format!("{}", nproc)]
-            }
+        let mut opts = Vec::new();
+        if self.is_multi_config() {
+            opts.push("--config".into());
+            opts.push(format!("{:?}", build_type));
         }
+        if self.is_ninja() || self.name().is_some_and(|name| name.contains("Makefiles")) {
+            opts.push("--".into());
+            opts.push("-j".into());
+            opts.push(format!("{}", nproc));
+        }
+        opts
     }
 }
 
@@ -240,17 +441,68 @@ pub struct EntrySetting {
     #[serde(default)]
     pub target: Vec<String>,
 
-    /// CMake Generator option (-G option in cmake)
-    #[serde(default)]
-    pub generator: CMakeGenerator,
+    /// CMake Generator option (-G option in cmake). Ninja is used if installed and not set.
+    pub generator: Option<CMakeGenerator>,
 
     ///  Option for `CMAKE_BUILD_TYPE`
     #[serde(default)]
     pub build_type: BuildType,
 
-    /// Additional LLVM build options
+    /// Additional LLVM build options, see [cmake](../cmake/index.html) module for the value types
+    #[serde(default)]
+    pub option: CacheOptions,
+
+    /// Build directory. A relative path is resolved from the source directory.
+    /// Default is `build` in the source directory, or `{build_root}/{name}` if `build_root` is set in config.toml.
+    pub build_dir: Option<String>,
+
+    /// Separate build directories for each build type, e.g. `build/Debug`.
+    /// Overrides `build_type_subdir` in config.toml.
+    pub build_type_subdir: Option<bool>,
+
+    /// Multi-stage build of clang, see [bootstrap](../bootstrap/index.html) module
+    pub bootstrap: Option<Bootstrap>,
+
+    /// Host C compiler: a path, a command name like `gcc-11`, or `build:{name}` for clang of an llvmmgmt build.
+    /// See [toolchain](../toolchain/index.html) module
+    pub compiler: Option<String>,
+
+    /// Cross compile for another host, see [toolchain](../toolchain/index.html) module
+    pub cross: Option<Cross>,
+
+    /// Compiler launcher, e.g. ccache. Overrides `launcher` in config.toml
+    pub launcher: Option<Launcher>,
+
+    /// Linker, e.g. lld. Overrides `linker` in config.toml
+    pub linker: Option<Linker>,
+
+    /// Build profiles applied on this setting in order, see [profile](../profile/index.html) module
+    #[serde(default)]
+    pub profile: Vec<String>,
+
+    /// Install stripped binaries
+    pub strip: Option<bool>,
+
+    /// Components to build and install instead of everything, see [install](../install/index.html) module
     #[serde(default)]
-    pub option: HashMap<String, String>,
+    pub install_components: Vec<String>,
+
+    /// Move debug info of the installed binaries into `.debug` directories next to them
+    pub split_debug_info: Option<bool>,
+
+    /// Build and install only these targets, e.g. `clang-format`. Set by `entry build --targets`.
+    #[serde(default)]
+    pub build_targets: Vec<String>,
+
+    /// Start from a minimal environment, see [environment](../environment/index.html) module
+    pub clean_env: Option<bool>,
+
+    /// Environment variables of cmake and the build tool
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+
+    /// Scripts run at fixed points of the build, see [hook](../hook/index.html) module
+    pub hooks: Option<Hooks>,
 }
 
 /// Describes how to compile LLVM/Clang
@@ -261,6 +513,7 @@ pub enum Entry {
     Remote {
         name: String,
         version: Option<Version>,
+        variant: Option<String>,
         url: String,
         tools: Vec<Tool>,
         setting: EntrySetting,
@@ -268,6 +521,7 @@ pub enum Entry {
     Local {
         name: String,
         version: Option<Version>,
+        variant: Option<String>,
         path: PathBuf,
         setting: EntrySetting,
     },
@@ -333,18 +587,16 @@ pub fn load_entries() -> Result<Vec<Entry>> {
 
 pub fn load_entry(name: &str) -> Result<Entry> {
     let entries = load_entries()?;
-    for entry in entries {
-        if entry.name() == name {
+    for mut entry in entries {
+        let matched = entry.name() == name
+            || match (entry.version(), VersionReq::parse(name)) {
+                (Some(version), Ok(req)) => req.matches(version),
+                _ => false,
+            };
+        if matched {
+            entry.apply_profiles()?;
             return Ok(entry);
         }
-
-        if let Some(version) = entry.version() {
-            if let Ok(req) = VersionReq::parse(name) {
-                if req.matches(version) {
-                    return Ok(entry);
-                }
-            }
-        }
     }
     Err(Error::InvalidEntry {
         message: "Entry not found".into(),
@@ -352,6 +604,19 @@ pub fn load_entry(name: &str) -> Result<Entry> {
     })
 }
 
+/// Entry with the profiles and then the variant applied.
+/// Builds are installed, verified, and compared with their entries in this order.
+pub fn load_entry_with(name: &str, variant: Option<&str>, profiles: &[String]) -> Result<Entry> {
+    let mut entry = load_entry(name)?;
+    for profile in profiles {
+        entry.apply_profile(profile)?;
+    }
+    if let Some(variant) = variant {
+        entry.apply_variant(variant)?;
+    }
+    Ok(entry)
+}
+
 lazy_static::lazy_static! {
     static ref LLVM_8_0_1: Version = Version::new(8, 0, 1);
     static ref LLVM_9_0_0: Version = Version::new(9, 0, 0);
@@ -421,7 +686,7 @@ impl Entry {
         Entry::parse_setting(&name, Some(version), setting).unwrap()
     }
 
-    fn parse_setting(name: &str, version: Option<Version>, setting: EntrySetting) -> Result<Self> {
+    pub(crate) fn parse_setting(name: &str, version: Option<Version>, setting: EntrySetting) -> Result<Self> {
         if setting.path.is_some() && setting.url.is_some() {
             return Err(Error::InvalidEntry {
                 name: name.into(),
@@ -435,7 +700,8 @@ impl Entry {
             return Ok(Entry::Local {
                 name: name.into(),
                 version,
-                path: PathBuf::from(shellexpand::full(&path).unwrap().to_string()),
+                variant: None,
+                path: expand_path(path),
                 setting,
             });
         }
@@ -443,6 +709,7 @@ impl Entry {
             return Ok(Entry::Remote {
                 name: name.into(),
                 version,
+                variant: None,
                 url: url.clone(),
                 tools: setting.tools.clone(),
                 setting,
@@ -470,7 +737,7 @@ impl Entry {
 
     pub fn set_builder(&mut self, generator: &str) -> Result<()> {
         let generator = CMakeGenerator::from_str(generator)?;
-        self.setting_mut().generator = generator;
+        self.setting_mut().generator = Some(generator);
         Ok(())
     }
 
@@ -479,6 +746,34 @@ impl Entry {
         Ok(())
     }
 
+    /// Layer a variant on the setting, see [variant](../variant/index.html) module
+    pub fn apply_variant(&mut self, name: &str) -> Result<()> {
+        variant::load_variant(name)?.apply_to(self.setting_mut());
+        match self {
+            Entry::Remote { variant, .. } => *variant = Some(name.into()),
+            Entry::Local { variant, .. } => *variant = Some(name.into()),
+        }
+        Ok(())
+    }
+
+    /// Layer a profile on the setting, see [profile](../profile/index.html) module
+    pub fn apply_profile(&mut self, name: &str) -> Result<()> {
+        profile::load_profile(name)?.apply_to(self.setting_mut());
+        let profiles = &mut self.setting_mut().profile;
+        if !profiles.iter().any(|profile| profile == name) {
+            profiles.push(name.into());
+        }
+        Ok(())
+    }
+
+    /// Layer the profiles referenced by the entry
+    fn apply_profiles(&mut self) -> Result<()> {
+        for name in self.setting().profile.clone() {
+            profile::load_profile(&name)?.apply_to(self.setting_mut());
+        }
+        Ok(())
+    }
+
     pub fn checkout(&self) -> Result<()> {
         match self {
             Entry::Remote { url, tools, .. } => {
@@ -492,7 +787,7 @@ impl Entry {
             }
             Entry::Local { .. } => {}
         }
-        Ok(())
+        hook::run(self, HookPoint::PostCheckout)
     }
 
     pub fn clean_cache_dir(&self) -> Result<()> {
@@ -517,6 +812,57 @@ impl Entry {
         Ok(())
     }
 
+    /// Sources with the revisions checked out, or at the head of the remotes if not checked out and not `offline`.
+    /// Offline, sources whose URLs do not tell Git or SVN are not resolved.
+    pub fn sources(&self, offline: bool) -> Result<Vec<Source>> {
+        let src_dir = self.src_dir()?;
+        let revision = |url: &str, path: &Path| {
+            if offline {
+                match Resource::from_url_offline(url)? {
+                    Some(resource) => resource.checkout_revision(path),
+                    None => Ok(None),
+                }
+            } else {
+                Resource::from_url(url)?.revision(path)
+            }
+        };
+        match self {
+            Entry::Remote { url, tools, .. } => {
+                let mut sources = vec![Source {
+                    name: "llvm".into(),
+                    url: url.clone(),
+                    revision: revision(url, &src_dir)?,
+                }];
+                for tool in tools {
+                    let path = src_dir.join(tool.rel_path());
+                    sources.push(Source {
+                        name: tool.name.clone(),
+                        url: tool.url.clone(),
+                        revision: revision(&tool.url, &path)?,
+                    });
+                }
+                Ok(sources)
+            }
+            Entry::Local { path, .. } => {
+                let url = path.to_string_lossy().into_owned();
+                let git = Resource::Git {
+                    url: url.clone(),
+                    branch: None,
+                };
+                Ok(vec![Source {
+                    name: "llvm".into(),
+                    url,
+                    revision: git.checkout_revision(path)?,
+                }])
+            }
+        }
+    }
+
+    /// Fingerprint of the entry, see [fingerprint](../fingerprint/index.html) module
+    pub fn fingerprint(&self) -> Result<Fingerprint> {
+        Fingerprint::new(self, false)
+    }
+
     pub fn name(&self) -> &str {
         match self {
             Entry::Remote { name, .. } => name,
@@ -531,6 +877,18 @@ impl Entry {
         }
     }
 
+    pub fn variant(&self) -> Option<&str> {
+        match self {
+            Entry::Remote { variant, .. } => variant.as_deref(),
+            Entry::Local { variant, .. } => variant.as_deref(),
+        }
+    }
+
+    /// Name of the build installed from this entry
+    pub fn build_name(&self) -> String {
+        variant::build_name(self.name(), self.variant())
+    }
+
     pub fn src_dir(&self) -> Result<PathBuf> {
         Ok(match self {
             Entry::Remote { name, .. } => cache_dir()?.join(name),
@@ -539,7 +897,7 @@ impl Entry {
     }
 
     pub fn build_dir(&self) -> Result<PathBuf> {
-        let dir = self.src_dir()?.join("build");
+        let dir = self.build_dir_path()?;
         if !dir.exists() {
             info!("Create build dir: {}", dir.display());
             fs::create_dir_all(&dir).with(&dir)?;
@@ -547,6 +905,40 @@ impl Entry {
         Ok(dir)
     }
 
+    /// Path of the build directory without creating it
+    fn build_dir_path(&self) -> Result<PathBuf> {
+        self.build_dir_with(Config::global()?)
+    }
+
+    /// Path of the build directory with the global setting
+    fn build_dir_with(&self, config: &Config) -> Result<PathBuf> {
+        let setting = self.setting();
+        let mut dir = match (&setting.build_dir, &config.build_root) {
+            (Some(dir), _) => self.src_dir()?.join(expand_path(dir)),
+            (None, Some(root)) => expand_path(root).join(self.name()),
+            (None, None) => self.src_dir()?.join("build"),
+        };
+        if let Some(variant) = self.variant() {
+            // Do not share the CMake cache with the plain build
+            let name = dir
+                .file_name()
+                .ok_or_else(|| Error::InvalidEntry {
+                    name: self.name().into(),
+                    message: format!("Build directory has no name: {}", dir.display()),
+                })?
+                .to_string_lossy()
+                .into_owned();
+            dir.set_file_name(variant::build_name(&name, Some(variant)));
+        }
+        if setting
+            .build_type_subdir
+            .unwrap_or(config.build_type_subdir)
+        {
+            dir = dir.join(format!("{:?}", setting.build_type));
+        }
+        Ok(dir)
+    }
+
     pub fn clean_build_dir(&self) -> Result<()> {
         let path = self.build_dir()?;
         info!("Remove build dir: {}", path.display());
@@ -555,31 +947,396 @@ impl Entry {
     }
 
     pub fn prefix(&self) -> Result<PathBuf> {
-        Ok(data_dir()?.join(self.name()))
+        Ok(data_dir()?.join(self.build_name()))
     }
 
-    pub fn build(&self, nproc: usize) -> Result<()> {
-        self.configure()?;
-        process::Command::new("cmake")
+    /// CMake generator from the entry, or the one used for the existing build directory,
+    /// or detected from the installed build tools
+    pub fn generator(&self) -> Result<CMakeGenerator> {
+        if let Some(generator) = &self.setting().generator {
+            return Ok(generator.clone());
+        }
+        // CMake refuses to change the generator of a configured build directory
+        let cache = self.build_dir_path()?.join("CMakeCache.txt");
+        if let Some(name) = fs::read_to_string(cache)
+            .ok()
+            .and_then(|cache| cached_generator(&cache))
+        {
+            return CMakeGenerator::from_str(&name);
+        }
+        Ok(CMakeGenerator::detect(&self.environment()?))
+    }
+
+    /// `install/strip` if `strip` is set, which is available only for Makefile and Ninja generators.
+    /// `install-distribution` for `install_components`. Stripped if `strip` is set unless `split_debug_info`,
+    /// which strips binaries after install.
+    fn install_target(&self) -> Result<&'static str> {
+        let setting = self.setting();
+        let mut strip = setting.strip == Some(true) && setting.split_debug_info != Some(true);
+        if setting.install_components.is_empty() && strip {
+            let generator = self.generator()?;
+            if generator.is_multi_config() && !generator.is_ninja() {
+                warn!("strip is not supported by the generator, install unstripped binaries");
+                strip = false;
+            }
+        }
+        Ok(match (setting.install_components.is_empty(), strip) {
+            (false, true) => "install-distribution-stripped",
+            (false, false) => "install-distribution",
+            (true, true) => "install/strip",
+            (true, false) => "install",
+        })
+    }
+
+    /// `distribution` for `install_components`, and everything otherwise
+    fn build_target(&self) -> Result<&'static str> {
+        if self.setting().install_components.is_empty() {
+            Ok(self.generator()?.all_target())
+        } else {
+            Ok("distribution")
+        }
+    }
+
+    /// Build and install stages. Only `build_targets` and their `install-{target}` are built if set.
+    fn stages(&self) -> Result<Vec<Stage>> {
+        let setting = self.setting();
+        if let Some(bootstrap) = &setting.bootstrap {
+            if !setting.build_targets.is_empty() {
+                return Err(Error::InvalidEntry {
+                    name: self.name().into(),
+                    message: "Build targets are not supported with bootstrap".into(),
+                });
+            }
+            return Ok(bootstrap.stages());
+        }
+        if setting.build_targets.is_empty() {
+            return Ok(vec![
+                Stage::new("build", &[self.build_target()?]),
+                Stage::new("install", &[self.install_target()?]),
+            ]);
+        }
+        // Per-target install targets of LLVM strip binaries by themselves with any generator
+        let strip = setting.strip == Some(true) && setting.split_debug_info != Some(true);
+        let install = setting
+            .build_targets
+            .iter()
+            .map(|target| {
+                if strip {
+                    format!("install-{}-stripped", target)
+                } else {
+                    format!("install-{}", target)
+                }
+            })
+            .collect();
+        Ok(vec![
+            Stage {
+                name: "build",
+                targets: setting.build_targets.clone(),
+            },
+            Stage {
+                name: "install",
+                targets: install,
+            },
+        ])
+    }
+
+    /// Environment of build processes from `env` and `clean_env`
+    pub fn environment(&self) -> Result<BuildEnv> {
+        BuildEnv::new(self.name(), self.setting())
+    }
+
+    /// Compiler launcher from the entry or config.toml
+    pub fn launcher(&self) -> Result<Launcher> {
+        Ok(match self.setting().launcher {
+            Some(launcher) => launcher,
+            None => Config::global()?.launcher.unwrap_or_default(),
+        })
+    }
+
+    /// Linker from the entry or config.toml
+    pub fn linker(&self) -> Result<Linker> {
+        Ok(match &self.setting().linker {
+            Some(linker) => linker.clone(),
+            None => Config::global()?.linker.clone().unwrap_or_default(),
+        })
+    }
+
+    /// Parallel jobs computed from this machine, config.toml, and command line options
+    pub fn jobs(&self, compile: Option<usize>, link: Option<usize>) -> Result<Jobs> {
+        let config = Config::global()?;
+        let mut jobs =
+            Jobs::detect(self.setting().build_type).with_override(config.jobs, config.link_jobs);
+        if let Some(safe) = self.previous_safe_jobs()? {
+            info!(
+                "Limit jobs by previous OOM-like failures: {} compile, {} link",
+                safe.compile, safe.link
+            );
+            jobs = jobs.limit(safe);
+        }
+        Ok(jobs.with_override(compile, link))
+    }
+
+    /// Priority and resource limits from config.toml and command line options
+    pub fn priority(&self, priority: Priority) -> Result<Priority> {
+        Ok(Config::global()?.priority.with_override(priority))
+    }
+
+    /// Safe jobs recorded by the installed build, and by a failed build in the build directory
+    fn previous_safe_jobs(&self) -> Result<Option<Jobs>> {
+        let installed = BuildInfo::load(&self.prefix()?)?.and_then(|info| info.safe_jobs);
+        let failed = BuildInfo::load(&self.build_dir_path()?)?.and_then(|info| info.safe_jobs);
+        Ok(match (installed, failed) {
+            (Some(installed), Some(failed)) => Some(installed.limit(failed)),
+            (installed, failed) => installed.or(failed),
+        })
+    }
+
+    /// Check that a partial build does not overwrite a full build in the same prefix
+    pub fn check_partial(&self) -> Result<()> {
+        if self.setting().build_targets.is_empty() {
+            return Ok(());
+        }
+        match BuildInfo::load(&self.prefix()?)? {
+            Some(info) if !info.is_partial() => Err(Error::InvalidBuild {
+                name: self.build_name(),
+                message: "Partial build would overwrite the full build. Use --force to install the targets into it"
+                    .into(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Targets installed into the prefix, including the ones of the previous partial builds
+    fn installed_targets(&self) -> Result<Vec<String>> {
+        let mut targets = match BuildInfo::load(&self.prefix()?)? {
+            Some(info) if !self.setting().build_targets.is_empty() => info.targets,
+            _ => Vec::new(),
+        };
+        for target in &self.setting().build_targets {
+            if !targets.contains(target) {
+                targets.push(target.clone());
+            }
+        }
+        Ok(targets)
+    }
+
+    /// Information recorded into the prefix after install
+    pub fn build_info(&self) -> Result<BuildInfo> {
+        Ok(BuildInfo {
+            entry: self.name().into(),
+            variant: self.variant().map(Into::into),
+            profiles: self.setting().profile.clone(),
+            components: self.setting().install_components.clone(),
+            targets: self.installed_targets()?,
+            env: self.environment()?.effective(),
+            host_triple: self
+                .setting()
+                .cross
+                .as_ref()
+                .map(|cross| cross.host_triple.clone()),
+            launcher: self.launcher()?,
+            linker: self.linker()?,
+            fingerprint: Some(self.fingerprint()?),
+            ..Default::default()
+        })
+    }
+
+    /// Configure, build, and install. The build output is shown as progress, or printed as is if `verbose`.
+    pub fn build(&self, jobs: Jobs, priority: Priority, verbose: bool) -> Result<()> {
+        self.build_with_timer(jobs, priority, verbose, StageTimer::default())
+    }
+
+    /// [build](#method.build) continuing the stages timed by `timer`, e.g. checkout
+    pub fn build_with_timer(
+        &self,
+        jobs: Jobs,
+        priority: Priority,
+        verbose: bool,
+        mut timer: StageTimer,
+    ) -> Result<()> {
+        let mut jobs = jobs;
+        let mut retries = Vec::new();
+        let safe_jobs = self.previous_safe_jobs()?;
+        let build_dir = self.build_dir()?;
+        if let Some((name, duration)) = stats::estimate(self, jobs)? {
+            info!(
+                "Estimated build time: {} (from build {})",
+                format_duration(duration),
+                name
+            );
+        }
+        let limits = priority.prepare(&self.build_name());
+        progress::clear_log(&build_dir.join(progress::BUILD_LOG))?;
+
+        timer.run("configure", || self.configure(jobs, &limits))?;
+        let mut peak_disk_usage = stats::disk_usage(&build_dir);
+        let built = self.stages().and_then(|stages| {
+            for stage in stages {
+                if stage.name == "install" {
+                    hook::run(self, HookPoint::PostBuild)?;
+                }
+                timer.run(stage.name, || {
+                    stage.targets.iter().try_for_each(|target| {
+                        self.cmake_build(target, &mut jobs, &mut retries, &limits, verbose)
+                    })
+                })?;
+                peak_disk_usage = peak_disk_usage.max(stats::disk_usage(&build_dir));
+            }
+            Ok(())
+        });
+        let safe_jobs = if retries.is_empty() {
+            safe_jobs
+        } else {
+            Some(jobs)
+        };
+        if let Err(e) = built {
+            // Keep the reduced jobs for the next build without touching the installed build
+            let info = BuildInfo {
+                safe_jobs,
+                retries,
+                ..self.build_info()?
+            };
+            if let Err(save_error) = info.save(&build_dir) {
+                warn!("Cannot record the failed build: {}", save_error);
+            }
+            return Err(e);
+        }
+        BuildInfo::remove(&build_dir)?;
+        if self.setting().split_debug_info == Some(true) {
+            let strip = self.setting().strip == Some(true);
+            timer.run("split debug info", || {
+                install::split_debug_info(&self.prefix()?, strip).map(|_| ())
+            })?;
+        }
+        print!("{timer}");
+
+        let mut info = self.build_info()?;
+        info.safe_jobs = safe_jobs;
+        info.retries = retries;
+        info.stats = Some(BuildStats::new(
+            self.setting().build_type,
+            jobs,
+            timer.finished(),
+            peak_disk_usage,
+            &build_dir,
+        ));
+        info.save(&self.prefix()?)?;
+        hook::run(self, HookPoint::PostInstall)
+    }
+
+    /// Run a test suite like `check-llvm` in the build directory, see [lit](../lit/index.html) module
+    pub fn test(
+        &self,
+        suite: &str,
+        filter: Option<&str>,
+        jobs: Jobs,
+        verbose: bool,
+    ) -> Result<TestReport> {
+        let build_dir = self.build_dir()?;
+        let output = build_dir.join(lit::LIT_OUTPUT);
+        if output.exists() {
+            fs::remove_file(&output).with(&output)?;
+        }
+        let log_path = build_dir.join(progress::test_log(suite));
+        progress::clear_log(&log_path)?;
+        let mut progress = Progress::new(suite, &log_path, verbose)?;
+        let result = process::Command::new("cmake")
             .args([
                 "--build",
-                &format!("{}", self.build_dir()?.display()),
+                &format!("{}", build_dir.display()),
                 "--target",
-                "install",
+                suite,
             ])
             .args(
-                self
-                    .setting()
-                    .generator
-                    .build_option(nproc, self.setting().build_type),
+                self.generator()?
+                    .build_option(jobs.compile, self.setting().build_type),
             )
-            .check_run()?;
-        Ok(())
+            .build_env(&self.environment()?)
+            .env("LIT_OPTS", lit::lit_opts(&output, filter))
+            .check_run_with(|line| progress.line(line));
+        progress.finish(&result);
+        // lit fails if any test fails, but the build of the test dependencies may also fail
+        if !output.exists() {
+            result?;
+            return Err(Error::InvalidEntry {
+                name: self.name().into(),
+                message: format!("{} did not write lit results", suite),
+            });
+        }
+        lit::record(&build_dir, suite, lit::read_output(&output)?)
     }
 
-    fn configure(&self) -> Result<()> {
+    /// `cmake --build`, which is retried with fewer jobs after OOM-like failures
+    fn cmake_build(
+        &self,
+        target: &str,
+        jobs: &mut Jobs,
+        retries: &mut Vec<Retry>,
+        limits: &Limits,
+        verbose: bool,
+    ) -> Result<()> {
+        loop {
+            let mut oom = None;
+            let log_path = self.build_dir()?.join(progress::BUILD_LOG);
+            let mut progress = Progress::new(target, &log_path, verbose)?;
+            let result = process::Command::new("cmake")
+                .args([
+                    "--build",
+                    &format!("{}", self.build_dir()?.display()),
+                    "--target",
+                    target,
+                ])
+                .args(
+                    self.generator()?
+                        .build_option(jobs.compile, self.setting().build_type),
+                )
+                .limits(limits)
+                .build_env(&self.environment()?)
+                .check_run_with(|line| {
+                    if oom.is_none() && jobs::is_oom_message(line) {
+                        oom = Some(line.to_string());
+                    }
+                    progress.line(line);
+                });
+            progress.finish(&result);
+            let reason = match result.as_ref().err().and_then(|e| jobs::oom_reason(e, oom)) {
+                Some(reason) => reason,
+                None => return result,
+            };
+            let reduced = match jobs.reduce() {
+                Some(reduced) => reduced,
+                None => return result,
+            };
+            warn!(
+                "Build of {} failed like OOM ({}), retry with {} compile and {} link jobs",
+                target, reason, reduced.compile, reduced.link
+            );
+            *jobs = reduced;
+            retries.push(Retry {
+                target: target.into(),
+                reason,
+                jobs: reduced,
+            });
+            // Update job pools of Ninja
+            let opts = jobs.options(&self.generator()?);
+            if !opts.is_empty() {
+                process::Command::new("cmake")
+                    .args(&opts)
+                    .arg(".")
+                    .current_dir(self.build_dir()?)
+                    .limits(limits)
+                    .build_env(&self.environment()?)
+                    .check_run()?;
+            }
+        }
+    }
+
+    fn configure(&self, jobs: Jobs, limits: &Limits) -> Result<()> {
+        hook::run(self, HookPoint::PreConfigure)?;
         let setting = self.setting();
-        let mut opts = setting.generator.option();
+        let env = self.environment()?;
+        let generator = self.generator()?.validate()?;
+        let mut opts = generator.option();
         opts.push(format!("{}", self.src_dir()?.display()));
 
         opts.push(format!(
@@ -588,15 +1345,17 @@ impl Entry {
         ));
         opts.push(format!("-DCMAKE_BUILD_TYPE={:?}", setting.build_type));
 
-        // Enable ccache if exists
-        if which::which("ccache").is_ok() {
-            opts.push("-DLLVM_CCACHE_BUILD=ON".into());
+        if let Some(cross) = &setting.cross {
+            opts.extend(cross.prepare(self)?);
+        } else if let Some(compiler) = &setting.compiler {
+            let compiler = Compiler::resolve(compiler, &env)?;
+            compiler.check(&env)?;
+            opts.extend(compiler.options());
         }
 
-        // Enable lld if exists
-        if which::which("lld").is_ok() {
-            opts.push("-DLLVM_ENABLE_LLD=ON".into());
-        }
+        opts.extend(jobs.options(&generator));
+        opts.extend(self.launcher()?.options(&env)?);
+        opts.extend(self.linker()?.options(&env)?);
 
         // Target architectures
         if !setting.target.is_empty() {
@@ -606,14 +1365,33 @@ impl Entry {
             ));
         }
 
+        if !setting.install_components.is_empty() {
+            if setting.bootstrap.is_some() {
+                warn!("install_components is not supported with bootstrap, install everything");
+            } else {
+                opts.push(install::components_option(&setting.install_components));
+            }
+        }
+
+        // Install targets of utilities like FileCheck exist only with LLVM_INSTALL_UTILS
+        if !setting.build_targets.is_empty() && !setting.option.contains_key("LLVM_INSTALL_UTILS") {
+            opts.push("-DLLVM_INSTALL_UTILS=ON".into());
+        }
+
         // Other options
         for (k, v) in &setting.option {
-            opts.push(format!("-D{k}={v}"));
+            opts.push(cmake::define(k, v));
+        }
+
+        if let Some(bootstrap) = &setting.bootstrap {
+            opts.extend(bootstrap.options(self)?);
         }
 
         process::Command::new("cmake")
             .args(&opts)
             .current_dir(self.build_dir()?)
+            .limits(limits)
+            .build_env(&env)
             .check_run()?;
         Ok(())
     }
@@ -622,6 +1400,7 @@ impl Entry {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cmake::CacheValue;
 
     #[test]
     fn parse_url() {
@@ -675,12 +1454,194 @@ mod tests {
             Entry::Local {
                 name: "path".into(),
                 version: Some(version),
+                variant: None,
                 path: PathBuf::from(shellexpand::full(path).unwrap().to_string()),
                 setting,
             }
         )
     }
 
+    #[test]
+    fn build_dir_setting() -> Result<()> {
+        let src = tempfile::TempDir::new().with("/tmp")?;
+        let setting = EntrySetting {
+            path: Some(src.path().display().to_string()),
+            build_dir: Some("out".into()),
+            build_type_subdir: Some(true),
+            build_type: BuildType::Debug,
+            ..Default::default()
+        };
+        let entry = Entry::parse_setting("build_dir", None, setting.clone())?;
+        assert_eq!(entry.build_dir()?, src.path().join("out/Debug"));
+
+        let config = Config {
+            build_root: Some("/scratch/llvmmgmt".into()),
+            build_type_subdir: true,
+            ..Default::default()
+        };
+        let mut entry = Entry::parse_setting(
+            "build_dir",
+            None,
+            EntrySetting {
+                build_dir: None,
+                build_type_subdir: None,
+                ..setting.clone()
+            },
+        )?;
+        assert_eq!(
+            entry.build_dir_with(&config)?,
+            PathBuf::from("/scratch/llvmmgmt/build_dir/Debug")
+        );
+        entry.apply_variant("asserts")?;
+        assert_eq!(
+            entry.build_dir_with(&config)?,
+            PathBuf::from("/scratch/llvmmgmt/build_dir-asserts/Debug")
+        );
+
+        // The root directory has no name to append the variant
+        let mut entry = Entry::parse_setting(
+            "build_dir",
+            None,
+            EntrySetting {
+                build_dir: Some("/".into()),
+                ..setting
+            },
+        )?;
+        entry.apply_variant("asserts")?;
+        assert!(entry.build_dir_with(&config).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn apply_variant() -> Result<()> {
+        let mut entry = Entry::official(18, 1, 8);
+        entry.apply_variant("debug")?;
+        assert_eq!(entry.variant(), Some("debug"));
+        assert_eq!(entry.build_name(), "18.1.8-debug");
+        assert_eq!(entry.setting().build_type, BuildType::Debug);
+        assert!(entry.apply_variant("no-such-variant").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn apply_profile() -> Result<()> {
+        let mut entry = Entry::official(18, 1, 8);
+        entry.apply_profile("minimal")?;
+        entry.apply_profile("dist")?;
+        entry.apply_profile("dist")?;
+        let setting = entry.setting();
+        assert_eq!(setting.profile, ["minimal", "dist"]);
+        assert_eq!(setting.target, ["host"]);
+        assert_eq!(setting.strip, Some(true));
+        assert_eq!(
+            setting.option.get("LLVM_INCLUDE_TESTS"),
+            Some(&CacheValue::Bool(false))
+        );
+        // Profiles do not change the build name unlike variants
+        assert_eq!(entry.build_name(), "18.1.8");
+        assert!(entry.apply_profile("no-such-profile").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn partial_build_stages() -> Result<()> {
+        let mut entry = Entry::official(18, 1, 8);
+        entry.setting_mut().build_targets = vec!["clang-format".into(), "FileCheck".into()];
+        entry.setting_mut().strip = Some(true);
+        let stages = entry.stages()?;
+        assert_eq!(stages[0].targets, ["clang-format", "FileCheck"]);
+        assert_eq!(
+            stages[1].targets,
+            [
+                "install-clang-format-stripped",
+                "install-FileCheck-stripped"
+            ]
+        );
+        assert!(entry.build_info()?.is_partial());
+
+        entry.setting_mut().bootstrap = Some(Default::default());
+        assert!(entry.stages().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn partial_over_full_build() -> Result<()> {
+        let src = tempfile::TempDir::new().with("/tmp")?;
+        let setting = EntrySetting {
+            path: Some(src.path().display().to_string()),
+            build_targets: vec!["FileCheck".into()],
+            ..Default::default()
+        };
+        let entry = Entry::parse_setting("partial", None, setting)?;
+        let prefix = entry.prefix()?;
+        fs::create_dir_all(&prefix).with(&prefix)?;
+        entry.check_partial()?;
+
+        // Targets of partial builds accumulate
+        BuildInfo {
+            entry: "partial".into(),
+            targets: vec!["clang-format".into()],
+            ..Default::default()
+        }
+        .save(&prefix)?;
+        entry.check_partial()?;
+        assert_eq!(entry.build_info()?.targets, ["clang-format", "FileCheck"]);
+
+        BuildInfo {
+            entry: "partial".into(),
+            ..Default::default()
+        }
+        .save(&prefix)?;
+        assert!(matches!(
+            entry.check_partial(),
+            Err(Error::InvalidBuild { .. })
+        ));
+        fs::remove_dir_all(&prefix).with(&prefix)?;
+        Ok(())
+    }
+
+    #[test]
+    fn generators_in_help() {
+        let help = "Usage\n\n  cmake [options] <path-to-source>\n\nGenerators\n\n\
+                    The following generators are available on this platform (* marks default):\n\
+                    * Unix Makefiles               = Generates standard UNIX makefiles.\n  \
+                    Ninja                        = Generates build.ninja files.\n  \
+                    Ninja Multi-Config           = Generates build-<Config>.ninja files.\n  \
+                    Visual Studio 17 2022        = Generates Visual Studio 2022 project files.\n                                 \
+                    Use -A option to specify architecture.\n  \
+                    Sublime Text 2 - Unix Makefiles\n                               \
+                    = Generates Sublime Text 2 project files.\n";
+        assert_eq!(
+            parse_generators(help),
+            [
+                "Unix Makefiles",
+                "Ninja",
+                "Ninja Multi-Config",
+                "Visual Studio 17 2022",
+                "Sublime Text 2 - Unix Makefiles"
+            ]
+        );
+    }
+
+    #[test]
+    fn generator_build_option() {
+        let opts = CMakeGenerator::NinjaMultiConfig.build_option(8, BuildType::Debug);
+        assert_eq!(opts, ["--config", "Debug", "--", "-j", "8"]);
+        let vs = CMakeGenerator::Other("Visual Studio 17 2022".into());
+        assert_eq!(
+            vs.build_option(8, BuildType::Release),
+            ["--config", "Release"]
+        );
+        assert_eq!(vs.all_target(), "ALL_BUILD");
+        assert!(CMakeGenerator::Other("MySuperBuilder".into())
+            .validate()
+            .is_err());
+        assert_eq!(
+            cached_generator("CMAKE_BUILD_TYPE:STRING=Release\nCMAKE_GENERATOR:INTERNAL=Ninja\n"),
+            Some("Ninja".into())
+        );
+    }
+
     macro_rules! checkout {
         ($major:expr, $minor:expr, $patch: expr) => {
             paste::item! {
@@ -0,0 +1,186 @@
+//! Environment variables of build processes
+//!
+//! `cmake` and the build tool inherit the environment of llvmmgmt by default,
+//! where stray `CC`, `CFLAGS` or `LDFLAGS` may break the build in confusing ways.
+//! `env` table of an entry sets variables, and `clean_env` starts from a minimal environment
+//! containing only the variables in [ALLOWED_VARS]:
+//!
+//! ```toml
+//! [my-local-llvm]
+//! path      = "/path/to/your/src"
+//! clean_env = true
+//!
+//! [my-local-llvm.env]
+//! PATH     = "/opt/cmake/bin:$PATH"  # environment variables are expanded
+//! CCACHE_DIR = "~/.ccache-llvm"
+//! ```
+//!
+//! The effective environment is recorded with the build. Without `clean_env`, only the variables
+//! set by the entry and the inherited ones in [BUILD_VARS] are recorded not to leak secrets into the prefix.
+
+use std::{collections::BTreeMap, env, path::PathBuf, process};
+
+use crate::{entry::EntrySetting, error::*};
+
+/// Variables kept by `clean_env`
+pub const ALLOWED_VARS: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "SHELL",
+    "TERM",
+    "LANG",
+    "LC_ALL",
+    "TMPDIR",
+    // Required by the toolchain on Windows
+    "SYSTEMROOT",
+    "TEMP",
+    "TMP",
+];
+
+/// Inherited variables which affect the build, recorded with the build
+pub const BUILD_VARS: &[&str] = &[
+    "PATH",
+    "CC",
+    "CXX",
+    "CFLAGS",
+    "CXXFLAGS",
+    "CPPFLAGS",
+    "LDFLAGS",
+    "CPATH",
+    "C_INCLUDE_PATH",
+    "CPLUS_INCLUDE_PATH",
+    "LIBRARY_PATH",
+    "LD_LIBRARY_PATH",
+    "PKG_CONFIG_PATH",
+    "CMAKE_PREFIX_PATH",
+    "CMAKE_GENERATOR",
+    "SDKROOT",
+    "MACOSX_DEPLOYMENT_TARGET",
+];
+
+/// Environment of build processes
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BuildEnv {
+    clean: bool,
+    vars: BTreeMap<String, String>,
+}
+
+impl BuildEnv {
+    /// From `env` and `clean_env` of an entry, where `~` and variables in the values are expanded
+    pub fn new(name: &str, setting: &EntrySetting) -> Result<Self> {
+        let vars = setting
+            .env
+            .iter()
+            .map(|(key, value)| {
+                let value = shellexpand::full(value).map_err(|e| Error::InvalidEntry {
+                    name: name.into(),
+                    message: format!("Cannot expand env.{key}: {e}"),
+                })?;
+                Ok((key.clone(), value.into_owned()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(BuildEnv {
+            clean: setting.clean_env.unwrap_or(false),
+            vars,
+        })
+    }
+
+    /// Set up the environment of a command, see [CommandExt::build_env](../error/trait.CommandExt.html)
+    pub fn apply<'a>(&self, command: &'a mut process::Command) -> &'a mut process::Command {
+        if self.clean {
+            command.env_clear();
+            for key in ALLOWED_VARS {
+                if let Ok(value) = env::var(key) {
+                    command.env(key, value);
+                }
+            }
+        }
+        command.envs(&self.vars)
+    }
+
+    /// Variable seen by build processes
+    pub fn var(&self, key: &str) -> Option<String> {
+        if let Some(value) = self.vars.get(key) {
+            return Some(value.clone());
+        }
+        if self.clean && !ALLOWED_VARS.contains(&key) {
+            return None;
+        }
+        env::var(key).ok()
+    }
+
+    /// Find a command in `PATH` of build processes
+    pub fn which(&self, cmd: &str) -> Option<PathBuf> {
+        let cwd = env::current_dir().ok()?;
+        which::which_in(cmd, self.var("PATH"), cwd).ok()
+    }
+
+    /// Variables seen by build processes, see the [module level document](index.html)
+    pub fn effective(&self) -> BTreeMap<String, String> {
+        let inherited = if self.clean { ALLOWED_VARS } else { BUILD_VARS };
+        let mut vars: BTreeMap<String, String> = inherited
+            .iter()
+            .filter_map(|key| env::var(key).ok().map(|value| (key.to_string(), value)))
+            .collect();
+        vars.extend(self.vars.clone());
+        vars
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean_env() -> Result<()> {
+        let mut setting = EntrySetting {
+            clean_env: Some(true),
+            ..Default::default()
+        };
+        setting
+            .env
+            .insert("LLVMMGMT_TEST_VAR".into(), "$HOME/llvm".into());
+        let build_env = BuildEnv::new("clean_env", &setting)?;
+
+        let (stdout, _) = build_env
+            .apply(process::Command::new("env").env("CFLAGS", "-O0"))
+            .check_output()?;
+        assert!(!stdout.lines().any(|line| line.starts_with("CFLAGS=")));
+        let expected = format!("LLVMMGMT_TEST_VAR={}/llvm", env::var("HOME").unwrap());
+        assert!(stdout.lines().any(|line| line == expected));
+
+        let effective = build_env.effective();
+        assert!(effective.contains_key("LLVMMGMT_TEST_VAR"));
+        assert!(effective
+            .keys()
+            .all(|key| key == "LLVMMGMT_TEST_VAR" || ALLOWED_VARS.contains(&key.as_str())));
+        Ok(())
+    }
+
+    #[test]
+    fn which_in_path() -> Result<()> {
+        let bin = tempfile::TempDir::new().with("/tmp")?;
+        let tool = bin.path().join("llvmmgmt-test-tool");
+        std::fs::write(&tool, "#!/bin/sh\n").with(&tool)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).with(&tool)?;
+        }
+        let mut setting = EntrySetting::default();
+        assert_eq!(
+            BuildEnv::new("path", &setting)?.which("llvmmgmt-test-tool"),
+            None
+        );
+        setting
+            .env
+            .insert("PATH".into(), format!("{}:$PATH", bin.path().display()));
+        assert_eq!(
+            BuildEnv::new("path", &setting)?.which("llvmmgmt-test-tool"),
+            Some(tool)
+        );
+        Ok(())
+    }
+}
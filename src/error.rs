@@ -1,6 +1,14 @@
-use std::{io, path::*, process};
+use std::{
+    io::{self, BufRead, BufReader, Read},
+    path::*,
+    process,
+    sync::mpsc,
+    thread,
+};
 use thiserror::Error;
 
+use crate::{environment::BuildEnv, priority::Limits};
+
 pub type Result<T> = ::std::result::Result<T, Error>;
 
 #[derive(Debug, Error)]
@@ -23,9 +31,15 @@ pub enum Error {
     #[error("Unsupported cmake build type: {build_type}")]
     UnsupportedBuildType { build_type: String },
 
+    #[error("Unsupported linker: {linker}")]
+    UnsupportedLinker { linker: String },
+
     #[error("Unsupported shell: {shell}")]
     UnsupportedShell { shell: String },
 
+    #[error("Unsupported compression: {compression}")]
+    UnsupportedCompression { compression: String },
+
     #[error("Configure file already exists: {path}")]
     ConfigureAlreadyExists { path: PathBuf },
 
@@ -47,12 +61,33 @@ pub enum Error {
         source: toml::ser::Error,
     },
 
+    #[error(transparent)]
+    InvalidJSON {
+        #[from]
+        source: serde_json::Error,
+    },
+
     #[error("Entry {name} is invalid: {message}")]
     InvalidEntry { name: String, message: String },
 
     #[error("Build {name} is invalid: {message}")]
     InvalidBuild { name: String, message: String },
 
+    #[error("Archive {path} is invalid: {message}")]
+    InvalidArchive { path: PathBuf, message: String },
+
+    #[error("Compiler {compiler} is invalid: {message}")]
+    InvalidCompiler { compiler: String, message: String },
+
+    #[error("Variant {name} is invalid: {message}")]
+    InvalidVariant { name: String, message: String },
+
+    #[error("Profile {name} is invalid: {message}")]
+    InvalidProfile { name: String, message: String },
+
+    #[error("Priority setting {name} is invalid: {message}")]
+    InvalidPriority { name: String, message: String },
+
     #[error("HTTP request does not succeed with {status}: {url}")]
     HttpError {
         url: String,
@@ -85,12 +120,24 @@ pub enum Error {
         stderr: Option<String>,
     },
 
+    #[error("Hook {hook} failed: {script}\n{output}")]
+    HookFailed {
+        hook: String,
+        script: String,
+        output: String,
+    },
+
+    #[error("Tests of {suite} regressed: {}", .tests.join(", "))]
+    TestRegression { suite: String, tests: Vec<String> },
+
     #[error("External command not found: {cmd}")]
     CommandNotFound { cmd: String },
 
     #[error("External command has been terminated by signal: {cmd}")]
     CommandTerminatedBySignal {
         cmd: String,
+        /// Signal number, which is not available on Windows
+        signal: Option<i32>,
         stdout: Option<String>,
         stderr: Option<String>,
     },
@@ -121,6 +168,64 @@ pub trait CommandExt {
     fn silent(&mut self) -> &mut Self;
     fn check_run(&mut self) -> Result<()>;
     fn check_output(&mut self) -> Result<(String, String)>;
+    /// Run with handling each line of stdout and stderr instead of writing them to terminal
+    fn check_run_with(&mut self, on_line: impl FnMut(&str)) -> Result<()>;
+    /// Run with priority and resource limits, see [priority](../priority/index.html) module
+    fn limits(&mut self, limits: &Limits) -> &mut Self;
+    /// Run with the environment of an entry, see [environment](../environment/index.html) module
+    fn build_env(&mut self, env: &BuildEnv) -> &mut Self;
+}
+
+fn check_status(cmd: String, st: process::ExitStatus) -> Result<()> {
+    match st.code() {
+        Some(errno) => {
+            if errno != 0 {
+                Err(Error::CommandError {
+                    errno,
+                    cmd,
+                    stdout: None,
+                    stderr: None,
+                })
+            } else {
+                Ok(())
+            }
+        }
+        None => Err(Error::CommandTerminatedBySignal {
+            cmd,
+            signal: terminated_by(st),
+            stdout: None,
+            stderr: None,
+        }),
+    }
+}
+
+/// Signal which terminated the process
+fn terminated_by(st: process::ExitStatus) -> Option<i32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        st.signal()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = st;
+        None
+    }
+}
+
+fn send_lines(reader: impl Read, tx: mpsc::Sender<String>) {
+    let mut reader = BufReader::new(reader);
+    let mut buf = Vec::new();
+    while let Ok(n) = reader.read_until(b'\n', &mut buf) {
+        if n == 0 {
+            break;
+        }
+        let line = String::from_utf8_lossy(&buf);
+        if tx.send(line.trim_end().to_string()).is_err() {
+            break;
+        }
+        buf.clear();
+    }
 }
 
 impl CommandExt for process::Command {
@@ -129,30 +234,46 @@ impl CommandExt for process::Command {
             .stderr(process::Stdio::null())
     }
 
+    fn limits(&mut self, limits: &Limits) -> &mut Self {
+        limits.apply(self)
+    }
+
+    fn build_env(&mut self, env: &BuildEnv) -> &mut Self {
+        env.apply(self)
+    }
+
     fn check_run(&mut self) -> Result<()> {
         let cmd = format!("{self:?}");
         let st = self
             .status()
             .map_err(|_| Error::CommandNotFound { cmd: cmd.clone() })?;
-        match st.code() {
-            Some(errno) => {
-                if errno != 0 {
-                    Err(Error::CommandError {
-                        errno,
-                        cmd,
-                        stdout: None,
-                        stderr: None,
-                    })
-                } else {
-                    Ok(())
-                }
-            }
-            None => Err(Error::CommandTerminatedBySignal {
-                cmd,
-                stdout: None,
-                stderr: None,
-            }),
+        check_status(cmd, st)
+    }
+
+    fn check_run_with(&mut self, mut on_line: impl FnMut(&str)) -> Result<()> {
+        let cmd = format!("{self:?}");
+        let mut child = self
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .spawn()
+            .map_err(|_| Error::CommandNotFound { cmd: cmd.clone() })?;
+        let (tx, rx) = mpsc::channel();
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+        let tx_err = tx.clone();
+        let readers = [
+            thread::spawn(move || send_lines(stdout, tx)),
+            thread::spawn(move || send_lines(stderr, tx_err)),
+        ];
+        // Ends when both of stdout and stderr are closed
+        for line in rx {
+            on_line(&line);
+        }
+        for reader in readers {
+            reader.join().expect("Reader thread panicked");
         }
+        let st = child.wait()?;
+        check_status(cmd, st)
     }
 
     fn check_output(&mut self) -> Result<(String, String)> {
@@ -177,6 +298,7 @@ impl CommandExt for process::Command {
             }
             None => Err(Error::CommandTerminatedBySignal {
                 cmd,
+                signal: terminated_by(output.status),
                 stdout: Some(stdout),
                 stderr: Some(stderr),
             }),
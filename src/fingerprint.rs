@@ -0,0 +1,357 @@
+//! Fingerprint of a fully resolved entry
+//!
+//! Two builds of an entry are interchangeable if they are built from the same sources with the same setting
+//! on the same kind of host. The fingerprint records them:
+//!
+//! - Sources: URLs and the revisions, i.e. commit SHAs of Git, revision numbers of SVN, and SHA-256 of Tar archives.
+//!   The revision checked out in the source directory is used, or the head of the remote if not checked out.
+//!   Uncommitted changes of a Git working tree are hashed into the revision.
+//!   Tar archives are identified after download, and local entries outside of Git are not identified.
+//! - The entry setting with profiles and variant applied, including the build type and CMake options.
+//!   Settings which do not change the installed files, e.g. `build_dir` and `launcher`, are cleared.
+//!   Variables of `env` are kept verbatim, except for machine-local ones like `PATH` and `CCACHE_DIR`;
+//!   the host compiler found in `PATH` is identified by its version instead.
+//! - CMake generator, host triple, and version of the host compiler.
+//!
+//! The SHA-256 of the fingerprint in JSON identifies builds in the [binary cache](../binary_cache/index.html).
+//!
+//! ```shell
+//! llvmmgmt entry fingerprint 18.1.8 --json
+//! ```
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fmt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    entry::{CMakeGenerator, Entry, EntrySetting},
+    environment::BuildEnv,
+    error::*,
+    toolchain::Compiler,
+};
+
+/// Variables of `env` which only locate tools and caches on the machine, and do not change the installed files
+const MACHINE_LOCAL_ENV: &[&str] = &["PATH", "HOME", "TMPDIR", "TMP", "TEMP"];
+
+/// Prefixes of the variables of compiler caches, e.g. `CCACHE_DIR` and `SCCACHE_CACHE_SIZE`
+const MACHINE_LOCAL_ENV_PREFIXES: &[&str] = &["CCACHE_", "SCCACHE_"];
+
+fn is_machine_local_env(key: &str) -> bool {
+    MACHINE_LOCAL_ENV.contains(&key)
+        || MACHINE_LOCAL_ENV_PREFIXES
+            .iter()
+            .any(|prefix| key.starts_with(prefix))
+}
+
+/// Source of an entry
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Source {
+    /// `llvm` for the main source, or the name of the tool
+    pub name: String,
+
+    /// URL, or path of a local entry
+    pub url: String,
+
+    /// Commit SHA for Git, revision number for SVN, or SHA-256 for Tar. `None` if not identified
+    pub revision: Option<String>,
+}
+
+/// Everything determining the installed files of an entry
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Fingerprint {
+    /// Name of the entry
+    pub entry: String,
+
+    /// Variant applied to the entry
+    pub variant: Option<String>,
+
+    pub sources: Vec<Source>,
+
+    /// Setting of the entry except the sources and machine-local settings
+    pub setting: EntrySetting,
+
+    pub generator: CMakeGenerator,
+
+    /// Triple of the host where the build runs
+    pub host_triple: String,
+
+    /// First line of `--version` of the host C compiler
+    pub compiler: Option<String>,
+}
+
+impl Fingerprint {
+    /// Resolve the sources, generator, and host compiler of the entry.
+    /// Sources not checked out are resolved by the heads of the remotes unless `offline`
+    pub fn new(entry: &Entry, offline: bool) -> Result<Self> {
+        let mut setting = entry.setting().clone();
+        let generator = match &setting.generator {
+            Some(generator) => generator.clone(),
+            None => entry.generator()?,
+        };
+        setting.linker = Some(entry.linker()?);
+        // Sources are resolved separately
+        setting.url = None;
+        setting.path = None;
+        setting.tools.clear();
+        setting.generator = None;
+        // Machine-local settings, and profile names which have been applied
+        setting.build_dir = None;
+        setting.build_type_subdir = None;
+        setting.launcher = None;
+        setting.profile.clear();
+        setting.env.retain(|key, _| !is_machine_local_env(key));
+
+        let env = entry.environment()?;
+        let cc = host_compiler(entry.setting(), &env);
+        let host_triple = match &entry.setting().cross {
+            Some(cross) => cross.host_triple.clone(),
+            None => compiler_output(&cc, "-dumpmachine", &env)
+                .unwrap_or_else(|| format!("{}-{}", env::consts::ARCH, env::consts::OS)),
+        };
+        Ok(Fingerprint {
+            entry: entry.name().into(),
+            variant: entry.variant().map(Into::into),
+            sources: entry.sources(offline)?,
+            setting,
+            generator,
+            host_triple,
+            compiler: compiler_output(&cc, "--version", &env),
+        })
+    }
+
+    /// Error if a source is not identified by its revision, where the fingerprint does not determine the build
+    pub fn check_sources(&self) -> Result<()> {
+        match self.sources.iter().find(|source| source.revision.is_none()) {
+            Some(source) => Err(Error::InvalidEntry {
+                name: self.entry.clone(),
+                message: format!(
+                    "Source {} in {} is not identified by a revision",
+                    source.name, source.url
+                ),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// SHA-256 of the fingerprint in JSON with sorted keys
+    pub fn hash(&self) -> String {
+        let value = serde_json::to_value(self).expect("Fingerprint is serializable");
+        format!("{:x}", Sha256::digest(value.to_string().as_bytes()))
+    }
+
+    /// Fields changed from this fingerprint to `other`,
+    /// e.g. `setting.option.LLVM_ENABLE_ASSERTIONS` or `sources.clang.revision`
+    pub fn diff(&self, other: &Fingerprint) -> Vec<FieldDiff> {
+        let mut old = BTreeMap::new();
+        let mut new = BTreeMap::new();
+        flatten(
+            "",
+            &serde_json::to_value(self).expect("Fingerprint is serializable"),
+            &mut old,
+        );
+        flatten(
+            "",
+            &serde_json::to_value(other).expect("Fingerprint is serializable"),
+            &mut new,
+        );
+        let fields: BTreeSet<_> = old.keys().chain(new.keys()).cloned().collect();
+        fields
+            .into_iter()
+            .filter_map(|field| {
+                let old = old.get(&field).cloned();
+                let new = new.get(&field).cloned();
+                (old != new).then_some(FieldDiff { field, old, new })
+            })
+            .collect()
+    }
+}
+
+/// Field of fingerprints with different values
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldDiff {
+    /// Dotted path of the field
+    pub field: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+impl fmt::Display for FieldDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |value: &Option<String>| value.clone().unwrap_or_else(|| "(none)".into());
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.field,
+            show(&self.old),
+            show(&self.new)
+        )
+    }
+}
+
+/// Leaf values of JSON by dotted paths. Arrays of named objects like sources are keyed by the names.
+fn flatten(path: &str, value: &Value, fields: &mut BTreeMap<String, String>) {
+    let join = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        }
+    };
+    match value {
+        Value::Null => {}
+        Value::Object(map) => {
+            for (key, value) in map {
+                flatten(&join(key), value, fields);
+            }
+        }
+        Value::Array(items)
+            if !items.is_empty() && items.iter().all(|item| item["name"].is_string()) =>
+        {
+            for item in items {
+                let name = join(item["name"].as_str().expect("named item"));
+                for (key, value) in item.as_object().expect("named object") {
+                    if key != "name" {
+                        flatten(&format!("{}.{}", name, key), value, fields);
+                    }
+                }
+            }
+        }
+        Value::String(s) => {
+            fields.insert(path.into(), s.clone());
+        }
+        value => {
+            fields.insert(path.into(), value.to_string());
+        }
+    }
+}
+
+/// C compiler used by CMake for the entry
+fn host_compiler(setting: &EntrySetting, env: &BuildEnv) -> PathBuf {
+    let spec = match (&setting.cross, &setting.compiler) {
+        (Some(cross), _) => cross.compiler.clone().unwrap_or_else(|| "clang".into()),
+        (None, Some(compiler)) => compiler.clone(),
+        (None, None) => env.var("CC").unwrap_or_else(|| "cc".into()),
+    };
+    match Compiler::resolve(&spec, env) {
+        Ok(compiler) => compiler.cc,
+        Err(_) => PathBuf::from(spec),
+    }
+}
+
+/// First line of the output of the compiler, or `None` if it does not run
+fn compiler_output(cc: &Path, arg: &str, env: &BuildEnv) -> Option<String> {
+    let (stdout, _) = Command::new(cc)
+        .arg(arg)
+        .build_env(env)
+        .check_output()
+        .ok()?;
+    stdout
+        .lines()
+        .next()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmake::CacheValue;
+
+    #[test]
+    fn fingerprint_of_setting() -> Result<()> {
+        let src = tempfile::TempDir::new().with("/tmp")?;
+        let setting = EntrySetting {
+            path: Some(src.path().to_string_lossy().into_owned()),
+            generator: Some(CMakeGenerator::Makefile),
+            ..Default::default()
+        };
+        let entry = Entry::parse_setting("local", None, setting.clone())?;
+        let fingerprint = Fingerprint::new(&entry, true)?;
+        assert_eq!(
+            fingerprint.sources,
+            [Source {
+                name: "llvm".into(),
+                url: src.path().to_string_lossy().into_owned(),
+                revision: None,
+            }]
+        );
+        assert_eq!(fingerprint.generator, CMakeGenerator::Makefile);
+        assert!(fingerprint.check_sources().is_err());
+        assert_eq!(fingerprint.hash(), Fingerprint::new(&entry, true)?.hash());
+
+        // Machine-local settings do not change the fingerprint
+        let mut local = setting.clone();
+        local.build_dir = Some("/scratch/local".into());
+        local.launcher = Some(crate::toolchain::Launcher::Ccache);
+        local
+            .env
+            .insert("CCACHE_DIR".into(), "/scratch/ccache".into());
+        local
+            .env
+            .insert("PATH".into(), "/opt/cmake/bin:/usr/bin:/bin".into());
+        let entry = Entry::parse_setting("local", None, local.clone())?;
+        assert_eq!(Fingerprint::new(&entry, true)?.hash(), fingerprint.hash());
+        local.env.insert("CXXFLAGS".into(), "-march=native".into());
+        let entry = Entry::parse_setting("local", None, local)?;
+        assert_ne!(Fingerprint::new(&entry, true)?.hash(), fingerprint.hash());
+
+        let mut option = setting;
+        option
+            .option
+            .insert("LLVM_ENABLE_ASSERTIONS".into(), CacheValue::Bool(true));
+        let entry = Entry::parse_setting("local", None, option)?;
+        assert_ne!(Fingerprint::new(&entry, true)?.hash(), fingerprint.hash());
+        Ok(())
+    }
+
+    #[test]
+    fn field_diff() -> Result<()> {
+        let old = Fingerprint {
+            entry: "llvm-main".into(),
+            variant: None,
+            sources: vec![
+                Source {
+                    name: "llvm".into(),
+                    url: "https://github.com/llvm/llvm-project".into(),
+                    revision: Some("aaaa".into()),
+                },
+                Source {
+                    name: "polly".into(),
+                    url: "https://github.com/llvm/polly".into(),
+                    revision: None,
+                },
+            ],
+            setting: EntrySetting::default(),
+            generator: CMakeGenerator::Ninja,
+            host_triple: "x86_64-linux-gnu".into(),
+            compiler: Some("gcc (GCC) 13.2.0".into()),
+        };
+        let mut new = old.clone();
+        new.sources[0].revision = Some("bbbb".into());
+        new.sources.pop();
+        new.setting.build_type = crate::entry::BuildType::Debug;
+        new.setting.target = vec!["X86".into()];
+        new.setting
+            .option
+            .insert("LLVM_ENABLE_ASSERTIONS".into(), CacheValue::Bool(true));
+        assert!(old.diff(&old).is_empty());
+        let diff: Vec<_> = old.diff(&new).iter().map(ToString::to_string).collect();
+        assert_eq!(
+            diff,
+            [
+                "setting.build_type: Release -> Debug",
+                "setting.option.LLVM_ENABLE_ASSERTIONS: (none) -> true",
+                "setting.target: [] -> [\"X86\"]",
+                "sources.llvm.revision: aaaa -> bbbb",
+                "sources.polly.url: https://github.com/llvm/polly -> (none)",
+            ]
+        );
+        Ok(())
+    }
+}
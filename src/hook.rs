@@ -0,0 +1,175 @@
+//! Hook scripts run at fixed points of building an entry
+//!
+//! ```toml
+//! [my-local-llvm.hooks]
+//! post_checkout = "./utils/regenerate-vendored.sh"
+//! pre_configure = "echo configure $LLVMMGMT_ENTRY"
+//! post_build    = "curl -fsS -d \"$LLVMMGMT_ENTRY built\" https://dashboard.example.com/notify"
+//! post_install  = "cp -r licenses \"$LLVMMGMT_PREFIX/share/\""
+//! ```
+//!
+//! Hooks are run by `sh -c` (`cmd /C` on Windows) in the source directory
+//! with the [environment](../environment/index.html) of the entry and the following variables:
+//!
+//! | Variable             | Value                                  |
+//! |:---------------------|:---------------------------------------|
+//! | `LLVMMGMT_HOOK`      | Name of the hook, e.g. `post_install`  |
+//! | `LLVMMGMT_ENTRY`     | Name of the entry                      |
+//! | `LLVMMGMT_SRC_DIR`   | Source directory                       |
+//! | `LLVMMGMT_BUILD_DIR` | Build directory                        |
+//! | `LLVMMGMT_PREFIX`    | Install prefix                         |
+//! | `LLVMMGMT_VERSION`   | Version of the entry, empty if unknown |
+//!
+//! A failing hook aborts the build with its output.
+//! Hooks do not run for a build fetched from the [binary cache](../binary_cache/index.html).
+
+use log::info;
+use serde_derive::{Deserialize, Serialize};
+use std::process::Command;
+
+use crate::{entry::Entry, error::*};
+
+/// Hook scripts, `hooks` table of an entry
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
+pub struct Hooks {
+    /// After the source is checked out, also for local entries
+    pub post_checkout: Option<String>,
+    /// Before cmake configures the build directory
+    pub pre_configure: Option<String>,
+    /// After the build, before install
+    pub post_build: Option<String>,
+    /// After install into the prefix
+    pub post_install: Option<String>,
+}
+
+/// Point where a hook runs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HookPoint {
+    PostCheckout,
+    PreConfigure,
+    PostBuild,
+    PostInstall,
+}
+
+impl HookPoint {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HookPoint::PostCheckout => "post_checkout",
+            HookPoint::PreConfigure => "pre_configure",
+            HookPoint::PostBuild => "post_build",
+            HookPoint::PostInstall => "post_install",
+        }
+    }
+}
+
+impl Hooks {
+    pub fn get(&self, point: HookPoint) -> Option<&str> {
+        match point {
+            HookPoint::PostCheckout => self.post_checkout.as_deref(),
+            HookPoint::PreConfigure => self.pre_configure.as_deref(),
+            HookPoint::PostBuild => self.post_build.as_deref(),
+            HookPoint::PostInstall => self.post_install.as_deref(),
+        }
+    }
+}
+
+/// Run the hook of an entry at the point if set
+pub fn run(entry: &Entry, point: HookPoint) -> Result<()> {
+    let script = match entry.setting().hooks.as_ref().and_then(|h| h.get(point)) {
+        Some(script) => script,
+        None => return Ok(()),
+    };
+    info!("Run {} hook: {}", point.name(), script);
+    let mut command = shell(script);
+    command
+        .build_env(&entry.environment()?)
+        .current_dir(entry.src_dir()?)
+        .env("LLVMMGMT_HOOK", point.name())
+        .env("LLVMMGMT_ENTRY", entry.name())
+        .env("LLVMMGMT_SRC_DIR", entry.src_dir()?)
+        .env("LLVMMGMT_BUILD_DIR", entry.build_dir()?)
+        .env("LLVMMGMT_PREFIX", entry.prefix()?)
+        .env(
+            "LLVMMGMT_VERSION",
+            entry.version().map(|v| v.to_string()).unwrap_or_default(),
+        );
+    let mut output = String::new();
+    command
+        .check_run_with(|line| {
+            println!("{line}");
+            output.push_str(line);
+            output.push('\n');
+        })
+        .map_err(|_| Error::HookFailed {
+            hook: point.name().into(),
+            script: script.into(),
+            output,
+        })
+}
+
+#[cfg(not(windows))]
+fn shell(script: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(script);
+    command
+}
+
+#[cfg(windows)]
+fn shell(script: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(script);
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::EntrySetting;
+
+    fn entry_with_hooks(src: &std::path::Path, hooks: Hooks) -> Result<Entry> {
+        let setting = EntrySetting {
+            path: Some(src.display().to_string()),
+            hooks: Some(hooks),
+            ..Default::default()
+        };
+        Entry::parse_setting("hook", None, setting)
+    }
+
+    #[test]
+    fn hook_env() -> Result<()> {
+        let src = tempfile::TempDir::new().with("/tmp")?;
+        let entry = entry_with_hooks(
+            src.path(),
+            Hooks {
+                post_checkout: Some("echo \"$LLVMMGMT_HOOK $LLVMMGMT_ENTRY\" > hook.txt".into()),
+                ..Default::default()
+            },
+        )?;
+        run(&entry, HookPoint::PostCheckout)?;
+        // Not set
+        run(&entry, HookPoint::PostInstall)?;
+        let written = std::fs::read_to_string(src.path().join("hook.txt")).with(src.path())?;
+        assert_eq!(written, "post_checkout hook\n");
+        Ok(())
+    }
+
+    #[test]
+    fn failing_hook() -> Result<()> {
+        let src = tempfile::TempDir::new().with("/tmp")?;
+        let entry = entry_with_hooks(
+            src.path(),
+            Hooks {
+                pre_configure: Some("echo broken; exit 3".into()),
+                ..Default::default()
+            },
+        )?;
+        match run(&entry, HookPoint::PreConfigure) {
+            Err(Error::HookFailed { hook, output, .. }) => {
+                assert_eq!(hook, "pre_configure");
+                assert_eq!(output, "broken\n");
+            }
+            result => panic!("Unexpected result: {result:?}"),
+        }
+        Ok(())
+    }
+}
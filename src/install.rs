@@ -0,0 +1,210 @@
+//! Install of distribution components and post-install processing
+//!
+//! A full install of LLVM puts gigabytes of static libraries and tools into the prefix.
+//! `install_components` limits the build and install to the components,
+//! using `LLVM_DISTRIBUTION_COMPONENTS` and the `install-distribution` target of the LLVM build system:
+//!
+//! ```toml
+//! [my-llvm]
+//! url                = "https://github.com/llvm/llvm-project"
+//! install_components = ["clang", "clang-resource-headers", "lld", "llvm-ar", "llvm-objcopy"]
+//! strip              = true  # install stripped binaries
+//! split_debug_info   = true  # move debug info into .debug directories
+//! ```
+//!
+//! With `split_debug_info`, debug info of the installed ELF executables and shared libraries
+//! is moved into `.debug/{name}.debug` next to them, e.g. `{prefix}/bin/.debug/clang-18.debug`,
+//! and linked by `.gnu_debuglink`. GDB and LLDB search the `.debug` directory next to a binary
+//! for its debug link, so the debug info is found without setting `debug-file-directory`,
+//! even after the build is moved to another prefix.
+
+use log::info;
+use std::{
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::error::*;
+
+/// Directory of the split debug info next to the binaries
+pub const DEBUG_DIR: &str = ".debug";
+
+/// `-DLLVM_DISTRIBUTION_COMPONENTS` option
+pub fn components_option(components: &[String]) -> String {
+    format!("-DLLVM_DISTRIBUTION_COMPONENTS={}", components.join(";"))
+}
+
+/// Split debug info of the binaries in the prefix, and returns the number of processed binaries
+pub fn split_debug_info(prefix: &Path, strip: bool) -> Result<usize> {
+    let objcopy = find_objcopy(prefix)?;
+    let binaries = installed_binaries(prefix)?;
+    for binary in &binaries {
+        let debug_dir = binary.parent().expect("binary in prefix").join(DEBUG_DIR);
+        let name = binary
+            .file_name()
+            .expect("binary in prefix")
+            .to_string_lossy();
+        let debug = debug_dir.join(format!("{name}.debug"));
+        fs::create_dir_all(&debug_dir).with(&debug_dir)?;
+        Command::new(&objcopy)
+            .arg("--only-keep-debug")
+            .arg(binary)
+            .arg(&debug)
+            .check_run()?;
+        Command::new(&objcopy)
+            .arg(if strip {
+                "--strip-unneeded"
+            } else {
+                "--strip-debug"
+            })
+            // Only the file name is recorded, which is looked up in the .debug directory
+            .arg(format!("--add-gnu-debuglink={}", debug.display()))
+            .arg(binary)
+            .check_run()?;
+    }
+    info!(
+        "Split debug info of {} binaries into {} directories",
+        binaries.len(),
+        DEBUG_DIR
+    );
+    Ok(binaries.len())
+}
+
+/// `llvm-objcopy` installed into the prefix if it runs on this machine, or objcopy in PATH
+fn find_objcopy(prefix: &Path) -> Result<PathBuf> {
+    let candidates = [
+        prefix.join("bin/llvm-objcopy"),
+        PathBuf::from("llvm-objcopy"),
+        PathBuf::from("objcopy"),
+    ];
+    candidates
+        .into_iter()
+        .find(|objcopy| {
+            Command::new(objcopy)
+                .arg("--version")
+                .silent()
+                .check_run()
+                .is_ok()
+        })
+        .ok_or_else(|| Error::CommandNotFound {
+            cmd: "objcopy".into(),
+        })
+}
+
+/// ELF executables and shared libraries in `bin` and `lib` of the prefix, except split debug info
+pub(crate) fn installed_binaries(prefix: &Path) -> Result<Vec<PathBuf>> {
+    let mut binaries = Vec::new();
+    for dir in ["bin", "lib"] {
+        collect_elf_binaries(&prefix.join(dir), &mut binaries)?;
+    }
+    Ok(binaries)
+}
+
+fn collect_elf_binaries(dir: &Path, binaries: &mut Vec<PathBuf>) -> Result<()> {
+    if !dir.is_dir() || dir.file_name().is_some_and(|name| name == DEBUG_DIR) {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir).with(dir)? {
+        let path = entry.with(dir)?.path();
+        // Symbolic links share the file with the link target
+        let meta = fs::symlink_metadata(&path).with(&path)?;
+        if meta.is_dir() {
+            collect_elf_binaries(&path, binaries)?;
+        } else if meta.is_file() && is_elf_binary(&path) {
+            binaries.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// ELF executable or shared library, i.e. not object files
+fn is_elf_binary(path: &Path) -> bool {
+    let mut header = [0u8; 18];
+    match fs::File::open(path).and_then(|mut f| f.read_exact(&mut header)) {
+        Ok(()) => elf_binary_header(&header),
+        Err(_) => false,
+    }
+}
+
+fn elf_binary_header(header: &[u8; 18]) -> bool {
+    const ET_EXEC: u16 = 2;
+    const ET_DYN: u16 = 3;
+    if &header[..4] != b"\x7fELF" {
+        return false;
+    }
+    let e_type = match header[5] {
+        1 => u16::from_le_bytes([header[16], header[17]]),
+        2 => u16::from_be_bytes([header[16], header[17]]),
+        _ => return false,
+    };
+    e_type == ET_EXEC || e_type == ET_DYN
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(magic: &[u8; 4], data: u8, e_type: u16) -> [u8; 18] {
+        let mut header = [0u8; 18];
+        header[..4].copy_from_slice(magic);
+        header[5] = data;
+        let bytes = if data == 1 {
+            e_type.to_le_bytes()
+        } else {
+            e_type.to_be_bytes()
+        };
+        header[16..].copy_from_slice(&bytes);
+        header
+    }
+
+    #[test]
+    fn elf_header() {
+        assert!(elf_binary_header(&header(b"\x7fELF", 1, 2)));
+        assert!(elf_binary_header(&header(b"\x7fELF", 2, 3)));
+        // Relocatable object
+        assert!(!elf_binary_header(&header(b"\x7fELF", 1, 1)));
+        assert!(!elf_binary_header(&header(b"!<ar", 1, 3)));
+    }
+
+    /// Name in `.gnu_debuglink`, which is followed by padding and CRC
+    fn debuglink(binary: &Path) -> Result<String> {
+        let section = binary.with_extension("debuglink");
+        Command::new("objcopy")
+            .arg(format!(
+                "--dump-section=.gnu_debuglink={}",
+                section.display()
+            ))
+            .arg(binary)
+            .check_run()?;
+        let content = fs::read(&section).with(&section)?;
+        let name = content.split(|b| *b == 0).next().unwrap_or_default();
+        Ok(String::from_utf8_lossy(name).into_owned())
+    }
+
+    /// Requires cc and objcopy
+    #[test]
+    fn split_debug_link() -> Result<()> {
+        let prefix = tempfile::TempDir::new().with("/tmp")?;
+        let bin = prefix.path().join("bin");
+        fs::create_dir_all(&bin).with(&bin)?;
+        let src = prefix.path().join("hello.c");
+        fs::write(&src, "int main(void) { return 0; }\n").with(&src)?;
+        let hello = bin.join("hello");
+        Command::new("cc")
+            .arg("-g")
+            .arg(&src)
+            .arg("-o")
+            .arg(&hello)
+            .check_run()?;
+
+        assert_eq!(split_debug_info(prefix.path(), false)?, 1);
+        let debug = bin.join(".debug/hello.debug");
+        assert!(debug.is_file());
+        assert_eq!(debuglink(&hello)?, "hello.debug");
+        // Split debug info is not processed again
+        assert_eq!(installed_binaries(prefix.path())?, [hello]);
+        Ok(())
+    }
+}
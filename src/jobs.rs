@@ -0,0 +1,293 @@
+//! Parallelism of compile and link jobs
+//!
+//! Linking LLVM/Clang takes several GB of memory for each job, and running as many link jobs as cores
+//! easily runs a workstation out of memory. The numbers of compile and link jobs are computed
+//! from the available memory and cores, and can be overridden by `--jobs` and `--link-jobs` options,
+//! or `jobs` and `link_jobs` in config.toml:
+//!
+//! ```toml
+//! jobs      = 16
+//! link_jobs = 2
+//! ```
+//!
+//! Link jobs are limited by `LLVM_PARALLEL_LINK_JOBS`, which is only effective with the Ninja generator.
+//!
+//! When a build fails like killed by the OOM killer, i.e. the build output contains messages like
+//! `Killed signal terminated program` or cmake itself is killed by SIGKILL, `cmake --build` is retried
+//! with fewer jobs until a floor of one compile and one link job. Builds interrupted by other signals,
+//! e.g. Ctrl-C, are not retried. The reduced jobs are recorded with the build, or in the build directory
+//! if the build fails, and limit the jobs of the next build of the entry.
+
+use log::info;
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+
+use crate::{
+    entry::{BuildType, CMakeGenerator},
+    error::Error,
+};
+
+/// Memory consumed by a compile job (MiB)
+const COMPILE_JOB_MEMORY: u64 = 1536;
+/// Memory consumed by a link job without debug info (MiB)
+const LINK_JOB_MEMORY: u64 = 4096;
+/// Memory consumed by a link job with debug info (MiB)
+const DEBUG_LINK_JOB_MEMORY: u64 = 10240;
+
+/// Number of parallel compile and link jobs
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Jobs {
+    pub compile: usize,
+    pub link: usize,
+}
+
+impl Jobs {
+    /// Compute from available memory and cores of this machine
+    pub fn detect(build_type: BuildType) -> Self {
+        let cores = num_cpus::get();
+        let jobs = match available_memory() {
+            Some(memory) => Self::from_memory(cores, memory, build_type),
+            // Unknown memory size, be conservative only for link jobs
+            None => Jobs {
+                compile: cores,
+                link: (cores / 4).max(1),
+            },
+        };
+        info!(
+            "Parallel jobs: {} compile, {} link",
+            jobs.compile, jobs.link
+        );
+        jobs
+    }
+
+    /// Compute from the number of cores and available memory in MiB
+    ///
+    /// ```
+    /// use llvmmgmt::{entry::BuildType, jobs::Jobs};
+    /// // 32 cores with 32GB memory
+    /// let jobs = Jobs::from_memory(32, 32 * 1024, BuildType::Release);
+    /// assert_eq!(jobs, Jobs { compile: 21, link: 8 });
+    /// let jobs = Jobs::from_memory(32, 32 * 1024, BuildType::Debug);
+    /// assert_eq!(jobs, Jobs { compile: 21, link: 3 });
+    /// ```
+    pub fn from_memory(cores: usize, memory: u64, build_type: BuildType) -> Self {
+        let link_memory = match build_type {
+            BuildType::Debug | BuildType::RelWithDebInfo => DEBUG_LINK_JOB_MEMORY,
+            BuildType::Release | BuildType::MinSizeRel => LINK_JOB_MEMORY,
+        };
+        let compile = ((memory / COMPILE_JOB_MEMORY) as usize).clamp(1, cores.max(1));
+        let link = ((memory / link_memory) as usize).clamp(1, compile);
+        Jobs { compile, link }
+    }
+
+    /// Override by user setting
+    pub fn with_override(self, compile: Option<usize>, link: Option<usize>) -> Self {
+        let compile = compile.unwrap_or(self.compile).max(1);
+        Jobs {
+            compile,
+            link: link.unwrap_or_else(|| self.link.min(compile)).max(1),
+        }
+    }
+
+    /// Limit by the jobs known to be safe
+    pub fn limit(self, safe: Jobs) -> Self {
+        Jobs {
+            compile: self.compile.min(safe.compile),
+            link: self.link.min(safe.link),
+        }
+    }
+
+    /// Halve link jobs first, and then compile jobs. `None` if it reaches the floor.
+    ///
+    /// ```
+    /// use llvmmgmt::jobs::Jobs;
+    /// let jobs = Jobs { compile: 8, link: 3 };
+    /// assert_eq!(jobs.reduce(), Some(Jobs { compile: 8, link: 1 }));
+    /// assert_eq!(Jobs { compile: 8, link: 1 }.reduce(), Some(Jobs { compile: 4, link: 1 }));
+    /// assert_eq!(Jobs { compile: 1, link: 1 }.reduce(), None);
+    /// ```
+    pub fn reduce(&self) -> Option<Jobs> {
+        if self.link > 1 {
+            Some(Jobs {
+                compile: self.compile,
+                link: self.link / 2,
+            })
+        } else if self.compile > 1 {
+            Some(Jobs {
+                compile: self.compile / 2,
+                link: 1,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Options for cmake
+    pub fn options(&self, generator: &CMakeGenerator) -> Vec<String> {
+        if generator.is_ninja() {
+            vec![
+                format!("-DLLVM_PARALLEL_COMPILE_JOBS={}", self.compile),
+                format!("-DLLVM_PARALLEL_LINK_JOBS={}", self.link),
+            ]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// Retry of `cmake --build` with fewer jobs
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Retry {
+    /// Build target which failed
+    pub target: String,
+    /// Why the failure is regarded as OOM
+    pub reason: String,
+    /// Jobs used for the retry
+    pub jobs: Jobs,
+}
+
+/// Messages of compilers and linkers killed by the OOM killer.
+/// Generic allocation failures, e.g. `out of memory`, also appear in test output and source paths,
+/// and are not retried.
+const OOM_MESSAGES: &[&str] = &[
+    "killed signal terminated program",
+    "terminated with signal 9",
+    "unable to execute command: killed",
+];
+
+/// Signal sent by the OOM killer
+const SIGKILL: i32 = 9;
+
+/// Check if a line of build output indicates a compiler or linker killed by the OOM killer
+pub fn is_oom_message(line: &str) -> bool {
+    let line = line.to_ascii_lowercase();
+    OOM_MESSAGES.iter().any(|message| line.contains(message))
+}
+
+/// Why the failure of `cmake --build` is regarded as OOM, from the error and the OOM-like line of the output.
+/// Builds interrupted by other signals than SIGKILL, e.g. Ctrl-C, are not regarded as OOM.
+pub fn oom_reason(error: &Error, oom_line: Option<String>) -> Option<String> {
+    match (error, oom_line) {
+        (_, Some(line)) => Some(line),
+        (
+            Error::CommandTerminatedBySignal {
+                signal: Some(SIGKILL),
+                ..
+            },
+            None,
+        ) => Some("cmake is killed by SIGKILL".into()),
+        _ => None,
+    }
+}
+
+/// Available memory in MiB read from `/proc/meminfo`
+fn available_memory() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    parse_meminfo(&meminfo)
+}
+
+fn parse_meminfo(meminfo: &str) -> Option<u64> {
+    let line = meminfo
+        .lines()
+        .find(|line| line.starts_with("MemAvailable:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib / 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meminfo() {
+        let meminfo = "MemTotal:       32795868 kB\nMemFree:         1234567 kB\nMemAvailable:   16777216 kB\n";
+        assert_eq!(parse_meminfo(meminfo), Some(16384));
+        assert_eq!(parse_meminfo("MemTotal: 1 kB\n"), None);
+    }
+
+    #[test]
+    fn oom_message() {
+        assert!(is_oom_message(
+            "collect2: fatal error: ld terminated with signal 9 [Killed]"
+        ));
+        assert!(is_oom_message(
+            "c++: fatal error: Killed signal terminated program cc1plus"
+        ));
+        assert!(is_oom_message(
+            "clang++: error: unable to execute command: Killed"
+        ));
+        assert!(!is_oom_message("LLVM ERROR: out of memory"));
+        assert!(!is_oom_message(
+            "FAIL: LLVM :: CodeGen/X86/cannot-allocate-memory.ll"
+        ));
+        assert!(!is_oom_message(
+            "[42/4242] Linking CXX executable bin/clang"
+        ));
+    }
+
+    #[test]
+    fn oom_failure() {
+        let killed = |signal| Error::CommandTerminatedBySignal {
+            cmd: "cmake".into(),
+            signal: Some(signal),
+            stdout: None,
+            stderr: None,
+        };
+        assert!(oom_reason(&killed(SIGKILL), None).is_some());
+        // SIGINT and SIGTERM
+        assert_eq!(oom_reason(&killed(2), None), None);
+        assert_eq!(oom_reason(&killed(15), None), None);
+        let failed = Error::CommandError {
+            errno: 1,
+            cmd: "cmake".into(),
+            stdout: None,
+            stderr: None,
+        };
+        assert_eq!(oom_reason(&failed, None), None);
+        assert_eq!(
+            oom_reason(&failed, Some("ld terminated with signal 9 [Killed]".into())).as_deref(),
+            Some("ld terminated with signal 9 [Killed]")
+        );
+    }
+
+    #[test]
+    fn low_memory() {
+        let jobs = Jobs::from_memory(8, 1024, BuildType::Release);
+        assert_eq!(
+            jobs,
+            Jobs {
+                compile: 1,
+                link: 1
+            }
+        );
+    }
+
+    #[test]
+    fn override_jobs() {
+        let jobs = Jobs {
+            compile: 8,
+            link: 2,
+        };
+        assert_eq!(
+            jobs.with_override(Some(4), None),
+            Jobs {
+                compile: 4,
+                link: 2
+            }
+        );
+        assert_eq!(
+            jobs.with_override(Some(1), None),
+            Jobs {
+                compile: 1,
+                link: 1
+            }
+        );
+        assert_eq!(
+            jobs.with_override(None, Some(4)),
+            Jobs {
+                compile: 8,
+                link: 4
+            }
+        );
+    }
+}
@@ -1,5 +1,26 @@
+pub mod archive;
+pub mod binary_cache;
+pub mod bootstrap;
 pub mod build;
+pub mod cmake;
 pub mod config;
 pub mod entry;
+pub mod environment;
 pub mod error;
+pub mod fingerprint;
+pub mod hook;
+pub mod install;
+pub mod jobs;
+pub mod lit;
+pub mod outdated;
+pub mod priority;
+pub mod profile;
+pub mod progress;
+pub mod relocate;
 pub mod resource;
+pub mod stats;
+#[cfg(test)]
+mod testing;
+pub mod toolchain;
+pub mod variant;
+pub mod verify;
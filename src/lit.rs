@@ -0,0 +1,272 @@
+//! Results of LLVM test suites run by lit
+//!
+//! `llvmmgmt entry test` builds a test target like `check-llvm` or `check-clang` in the build directory,
+//! where lit writes the results in JSON form:
+//!
+//! ```shell
+//! llvmmgmt entry test my-llvm --suite check-clang --filter 'CodeGen/X86'
+//! ```
+//!
+//! Results are recorded in the build directory for each suite. A test which passed
+//! (or failed as expected) in the previous run and fails now is a regression,
+//! and the command exits with an error. The first run of a suite has nothing to compare with;
+//! its results are recorded as the baseline and no failure is a regression.
+
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::error::*;
+
+/// JSON output of lit written in the build directory
+pub const LIT_OUTPUT: &str = "llvmmgmt-lit.json";
+
+/// Result code of a test in lit
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ResultCode {
+    Pass,
+    FlakyPass,
+    Xfail,
+    Fail,
+    Xpass,
+    Unresolved,
+    Unsupported,
+    Timeout,
+    Excluded,
+    Skipped,
+}
+
+impl ResultCode {
+    pub fn is_failure(&self) -> bool {
+        matches!(
+            self,
+            ResultCode::Fail | ResultCode::Xpass | ResultCode::Unresolved | ResultCode::Timeout
+        )
+    }
+
+    fn is_success(&self) -> bool {
+        matches!(
+            self,
+            ResultCode::Pass | ResultCode::FlakyPass | ResultCode::Xfail
+        )
+    }
+}
+
+/// Result codes of the tests, e.g. `LLVM :: CodeGen/X86/add.ll`
+pub type TestResults = BTreeMap<String, ResultCode>;
+
+#[derive(Deserialize)]
+struct LitOutput {
+    tests: Vec<LitTest>,
+}
+
+#[derive(Deserialize)]
+struct LitTest {
+    name: String,
+    code: ResultCode,
+}
+
+/// Read the output of `lit --output`
+pub fn read_output(path: &Path) -> Result<TestResults> {
+    let output: LitOutput = serde_json::from_str(&fs::read_to_string(path).with(path)?)?;
+    Ok(output
+        .tests
+        .into_iter()
+        .map(|test| (test.name, test.code))
+        .collect())
+}
+
+/// Summary of a test run compared with the previous run
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestReport {
+    pub suite: String,
+    pub passed: usize,
+    pub failed: usize,
+    pub xfailed: usize,
+    pub skipped: usize,
+    pub failures: Vec<String>,
+    pub regressions: Vec<String>,
+    /// No previous results, the run is recorded as the baseline
+    pub baseline: bool,
+}
+
+impl TestReport {
+    pub fn new(suite: &str, current: &TestResults, previous: Option<&TestResults>) -> Self {
+        let mut report = TestReport {
+            suite: suite.into(),
+            passed: 0,
+            failed: 0,
+            xfailed: 0,
+            skipped: 0,
+            failures: Vec::new(),
+            regressions: Vec::new(),
+            baseline: previous.is_none(),
+        };
+        for (name, code) in current {
+            match code {
+                ResultCode::Pass | ResultCode::FlakyPass => report.passed += 1,
+                ResultCode::Xfail => report.xfailed += 1,
+                code if code.is_failure() => report.failed += 1,
+                _ => report.skipped += 1,
+            }
+            if !code.is_failure() {
+                continue;
+            }
+            report.failures.push(name.clone());
+            let regressed = previous
+                .and_then(|previous| previous.get(name))
+                .is_some_and(|code| code.is_success());
+            if regressed {
+                report.regressions.push(name.clone());
+            }
+        }
+        report
+    }
+
+    /// Error if any test regressed
+    pub fn check(&self) -> Result<()> {
+        if self.regressions.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::TestRegression {
+                suite: self.suite.clone(),
+                tests: self.regressions.clone(),
+            })
+        }
+    }
+}
+
+impl fmt::Display for TestReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{}: {} passed, {} failed, {} expectedly failed, {} unsupported or skipped",
+            self.suite, self.passed, self.failed, self.xfailed, self.skipped
+        )?;
+        if self.baseline {
+            writeln!(f, "No previous results, recorded as the baseline")?;
+        }
+        if !self.failures.is_empty() {
+            writeln!(f, "Failed tests:")?;
+            for name in &self.failures {
+                let mark = if self.regressions.contains(name) {
+                    " (regression)"
+                } else {
+                    ""
+                };
+                writeln!(f, "  {}{}", name, mark)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn record_path(build_dir: &Path, suite: &str) -> PathBuf {
+    build_dir.join(format!("llvmmgmt-test-{}.json", suite))
+}
+
+/// Compare the results with the previous run of the suite, and record them.
+/// Results of a filtered run update only the tests in it.
+pub fn record(build_dir: &Path, suite: &str, current: TestResults) -> Result<TestReport> {
+    let path = record_path(build_dir, suite);
+    let previous: Option<TestResults> = if path.exists() {
+        Some(serde_json::from_str(
+            &fs::read_to_string(&path).with(&path)?,
+        )?)
+    } else {
+        None
+    };
+    let report = TestReport::new(suite, &current, previous.as_ref());
+    let mut results = previous.unwrap_or_default();
+    results.extend(current);
+    fs::write(&path, serde_json::to_string_pretty(&results)?).with(&path)?;
+    Ok(report)
+}
+
+/// `LIT_OPTS` value, which lit splits like a shell
+pub fn lit_opts(output: &Path, filter: Option<&str>) -> String {
+    let mut opts = vec![format!("--output={}", output.display())];
+    if let Some(filter) = filter {
+        opts.push(format!("--filter={}", filter));
+    }
+    opts.iter()
+        .map(|opt| format!("'{}'", opt.replace('\'', r"'\''")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_output(dir: &Path, tests: &[(&str, &str)]) -> Result<TestResults> {
+        let tests: Vec<_> = tests
+            .iter()
+            .map(|(name, code)| serde_json::json!({ "name": name, "code": code, "elapsed": 0.1 }))
+            .collect();
+        let output =
+            serde_json::json!({ "__version__": [17, 0, 0], "elapsed": 1.0, "tests": tests });
+        let path = dir.join(LIT_OUTPUT);
+        fs::write(&path, output.to_string()).with(&path)?;
+        read_output(&path)
+    }
+
+    #[test]
+    fn regression() -> Result<()> {
+        let build_dir = tempfile::TempDir::new().with("/tmp")?;
+        let first = write_output(
+            build_dir.path(),
+            &[
+                ("LLVM :: a.ll", "PASS"),
+                ("LLVM :: b.ll", "FAIL"),
+                ("LLVM :: c.ll", "XFAIL"),
+                ("LLVM :: d.ll", "UNSUPPORTED"),
+            ],
+        )?;
+        let report = record(build_dir.path(), "check-llvm", first)?;
+        assert_eq!(
+            (report.passed, report.failed, report.xfailed, report.skipped),
+            (1, 1, 1, 1)
+        );
+        // The first run is the baseline
+        assert!(report.baseline);
+        assert_eq!(report.failures, ["LLVM :: b.ll"]);
+        report.check()?;
+        assert!(record_path(build_dir.path(), "check-llvm").exists());
+
+        // Filtered run
+        let second = write_output(
+            build_dir.path(),
+            &[("LLVM :: a.ll", "FAIL"), ("LLVM :: b.ll", "FAIL")],
+        )?;
+        let report = record(build_dir.path(), "check-llvm", second)?;
+        assert!(!report.baseline);
+        assert_eq!(report.failures, ["LLVM :: a.ll", "LLVM :: b.ll"]);
+        assert_eq!(report.regressions, ["LLVM :: a.ll"]);
+        assert!(matches!(report.check(), Err(Error::TestRegression { .. })));
+
+        let third = write_output(
+            build_dir.path(),
+            &[("LLVM :: a.ll", "FAIL"), ("LLVM :: c.ll", "XPASS")],
+        )?;
+        let report = record(build_dir.path(), "check-llvm", third)?;
+        assert_eq!(report.regressions, ["LLVM :: c.ll"]);
+
+        // Other suites are recorded separately
+        let other = write_output(build_dir.path(), &[("Clang :: a.c", "PASS")])?;
+        record(build_dir.path(), "check-clang", other)?.check()?;
+        Ok(())
+    }
+
+    #[test]
+    fn quote_lit_opts() {
+        assert_eq!(
+            lit_opts(Path::new("/build dir/out.json"), Some("it's")),
+            r"'--output=/build dir/out.json' '--filter=it'\''s'"
+        );
+    }
+}
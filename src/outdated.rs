@@ -0,0 +1,274 @@
+//! Builds outdated by changes of their entries
+//!
+//! The [fingerprint](../fingerprint/index.html) of the entry is recorded in the build at install.
+//! `llvmmgmt outdated` compares it with the fingerprint of the current entry definition,
+//! and the revisions of Git and SVN sources with the heads of the upstream branches:
+//!
+//! ```shell
+//! $ llvmmgmt outdated
+//! llvm-main (entry llvm-main)
+//!   setting.option.LLVM_ENABLE_ASSERTIONS: (none) -> true
+//!   sources.llvm.revision: 3b5b5c1e -> 9d1f2a07
+//!   toolchain compiler: gcc (GCC) 13.2.0 -> gcc (GCC) 14.1.0
+//!   upstream llvm: 3b5b5c1e -> 9d1f2a07
+//! ```
+//!
+//! A change of `entry.toml`, a profile, or a variant, and a source updated by `llvmmgmt entry update` appear as
+//! changed fields. Changes of the host compiler, linker, and host triple are listed separately as `toolchain`.
+//! Uncommitted changes of a source are compared with the upstream as the commit they are made on.
+//! `--offline` skips the upstream heads, and does not resolve sources which are not checked out,
+//! or whose URLs do not tell Git or SVN without `git ls-remote`.
+//! Builds without a recorded fingerprint, e.g. installed by an older llvmmgmt,
+//! and builds which cannot be compared, e.g. of a removed entry, are reported as unknown.
+
+use log::warn;
+use std::fmt;
+
+use crate::{
+    build::Build,
+    entry::Entry,
+    fingerprint::{FieldDiff, Fingerprint},
+    resource::Resource,
+};
+
+/// Comparison of a build with its current entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub build: String,
+    pub entry: Option<String>,
+    /// Fields of the entry changed from the recorded fingerprint to the current one
+    pub changes: Vec<FieldDiff>,
+    /// Changes of the host compiler, linker, and host triple
+    pub toolchain: Vec<FieldDiff>,
+    /// Sources whose upstream head differs from the built revision
+    pub upstream: Vec<FieldDiff>,
+    /// Why the build cannot be compared
+    pub unknown: Option<String>,
+}
+
+impl Report {
+    fn unknown(build: &Build, entry: Option<String>, message: String) -> Self {
+        Report {
+            build: build.name().into(),
+            entry,
+            changes: Vec::new(),
+            toolchain: Vec::new(),
+            upstream: Vec::new(),
+            unknown: Some(message),
+        }
+    }
+
+    pub fn is_outdated(&self) -> bool {
+        !self.changes.is_empty() || !self.toolchain.is_empty() || !self.upstream.is_empty()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.entry {
+            Some(entry) => writeln!(f, "{} (entry {})", self.build, entry)?,
+            None => writeln!(f, "{}", self.build)?,
+        }
+        if let Some(message) = &self.unknown {
+            writeln!(f, "  unknown: {}", message)?;
+        }
+        for change in &self.changes {
+            writeln!(f, "  {}", change)?;
+        }
+        for toolchain in &self.toolchain {
+            writeln!(f, "  toolchain {}", toolchain)?;
+        }
+        for upstream in &self.upstream {
+            writeln!(f, "  upstream {}", upstream)?;
+        }
+        Ok(())
+    }
+}
+
+/// Fields of the fingerprint determined by the host rather than the entry
+const TOOLCHAIN_FIELDS: &[&str] = &["compiler", "host_triple", "setting.linker"];
+
+/// Compare the build with its entry, and with the upstream heads unless `offline`.
+/// Errors are reported as unknown not to stop checking other builds.
+pub fn check(build: &Build, offline: bool) -> Report {
+    let info = match build.info() {
+        Ok(Some(info)) => info,
+        Ok(None) => return Report::unknown(build, None, "Not installed by llvmmgmt".into()),
+        Err(e) => return Report::unknown(build, None, e.to_string()),
+    };
+    let entry_name = Some(info.entry.clone());
+    let recorded = match &info.fingerprint {
+        Some(fingerprint) => fingerprint,
+        None => return Report::unknown(build, entry_name, "Fingerprint is not recorded".into()),
+    };
+    let entry = match info.load_entry() {
+        Ok(entry) => entry,
+        Err(e) => return Report::unknown(build, entry_name, e.to_string()),
+    };
+    let current = match Fingerprint::new(&entry, offline) {
+        Ok(current) => current,
+        Err(e) => return Report::unknown(build, entry_name, e.to_string()),
+    };
+    let upstream = match entry {
+        Entry::Remote { .. } if !offline => upstream_changes(recorded),
+        _ => Vec::new(),
+    };
+    let (toolchain, changes) = recorded
+        .diff(&current)
+        .into_iter()
+        .partition(|diff| TOOLCHAIN_FIELDS.contains(&diff.field.as_str()));
+    Report {
+        build: build.name().into(),
+        entry: entry_name,
+        changes,
+        toolchain,
+        upstream,
+        unknown: None,
+    }
+}
+
+/// Sources whose upstream head differs from the recorded revision.
+/// Sources which cannot be reached are skipped with a warning.
+fn upstream_changes(recorded: &Fingerprint) -> Vec<FieldDiff> {
+    let mut changes = Vec::new();
+    for source in &recorded.sources {
+        // Local changes are compared with the commit they are made on
+        let revision = match &source.revision {
+            Some(revision) => revision.split("-dirty-").next().unwrap_or(revision),
+            None => continue,
+        };
+        let head = Resource::from_url(&source.url).and_then(|resource| resource.remote_revision());
+        match head {
+            Ok(Some(head)) if head != revision => changes.push(FieldDiff {
+                field: source.name.clone(),
+                old: Some(revision.to_string()),
+                new: Some(head),
+            }),
+            Ok(_) => {}
+            Err(e) => warn!("Cannot get the upstream head of {}: {}", source.url, e),
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{build::BuildInfo, cmake::CacheValue, error::*, fingerprint::Source};
+    use std::{fs, process::Command};
+
+    #[test]
+    fn unknown_builds() -> Result<()> {
+        let data_dir = tempfile::TempDir::new().with("/tmp")?;
+        let prefix = data_dir.path().join("old");
+        fs::create_dir_all(&prefix).with(&prefix)?;
+        let build = Build::from_path(&prefix);
+        let report = check(&build, true);
+        assert_eq!(report.unknown.as_deref(), Some("Not installed by llvmmgmt"));
+
+        BuildInfo {
+            entry: "18.1.8".into(),
+            ..Default::default()
+        }
+        .save(&prefix)?;
+        let report = check(&build, true);
+        assert_eq!(report.entry.as_deref(), Some("18.1.8"));
+        assert_eq!(
+            report.unknown.as_deref(),
+            Some("Fingerprint is not recorded")
+        );
+        assert!(!report.is_outdated());
+        assert_eq!(
+            report.to_string(),
+            "old (entry 18.1.8)\n  unknown: Fingerprint is not recorded\n"
+        );
+
+        // Removed entry
+        BuildInfo {
+            entry: "no-such-entry".into(),
+            fingerprint: Some(Fingerprint::new(&Entry::official(18, 1, 8), true)?),
+            ..Default::default()
+        }
+        .save(&prefix)?;
+        let report = check(&build, true);
+        assert!(report.unknown.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn outdated_build() -> Result<()> {
+        let data_dir = tempfile::TempDir::new().with("/tmp")?;
+        let prefix = data_dir.path().join("18.1.8");
+        fs::create_dir_all(&prefix).with(&prefix)?;
+        let build = Build::from_path(&prefix);
+        let mut recorded = Fingerprint::new(&Entry::official(18, 1, 8), true)?;
+        let info = BuildInfo {
+            entry: "18.1.8".into(),
+            fingerprint: Some(recorded.clone()),
+            ..Default::default()
+        };
+        info.save(&prefix)?;
+        let report = check(&build, true);
+        assert_eq!(report.unknown, None);
+        assert!(!report.is_outdated());
+
+        recorded
+            .setting
+            .option
+            .insert("LLVM_ENABLE_ASSERTIONS".into(), CacheValue::Bool(true));
+        recorded.compiler = Some("gcc (GCC) 4.8.5".into());
+        BuildInfo {
+            fingerprint: Some(recorded.clone()),
+            ..info
+        }
+        .save(&prefix)?;
+        let report = check(&build, true);
+        assert!(report.is_outdated());
+        assert_eq!(
+            report
+                .changes
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            ["setting.option.LLVM_ENABLE_ASSERTIONS: true -> (none)"]
+        );
+        assert_eq!(report.toolchain.len(), 1);
+        assert_eq!(report.toolchain[0].field, "compiler");
+        assert!(report.upstream.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn upstream_of_dirty_source() -> Result<()> {
+        let repo = tempfile::TempDir::new().with("/tmp")?;
+        let git = |args: &[&str]| {
+            Command::new("git")
+                .args([
+                    "-c",
+                    "user.name=llvmmgmt",
+                    "-c",
+                    "user.email=llvmmgmt@localhost",
+                ])
+                .args(args)
+                .current_dir(repo.path())
+                .check_output()
+        };
+        git(&["init", "-q", "-b", "main"])?;
+        git(&["commit", "-q", "--allow-empty", "-m", "init"])?;
+        let (sha, _) = git(&["rev-parse", "HEAD"])?;
+
+        let mut recorded = Fingerprint::new(&Entry::official(18, 1, 8), true)?;
+        recorded.sources = vec![Source {
+            name: "llvm".into(),
+            url: format!("file://{}", repo.path().display()),
+            revision: Some(format!("{}-dirty-0123456789abcdef", sha.trim())),
+        }];
+        assert!(upstream_changes(&recorded).is_empty());
+
+        git(&["commit", "-q", "--allow-empty", "-m", "update"])?;
+        let changes = upstream_changes(&recorded);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].old.as_deref(), Some(sha.trim()));
+        Ok(())
+    }
+}
@@ -0,0 +1,362 @@
+//! Priority and resource limits of build processes
+//!
+//! A build of LLVM occupies all cores, disks, and memory of the machine.
+//! `cmake` and the build tool run by llvmmgmt can be deprioritized by `--nice`, `--ionice`,
+//! and `--memory-max` options of `install` and `entry build`, or `priority` table in config.toml:
+//!
+//! ```toml
+//! [priority]
+//! nice       = 10
+//! ionice     = "idle"  # or "best-effort:7"
+//! memory_max = "24G"
+//! ```
+//!
+//! - `nice` is set by `setpriority(2)`, and inherited by compilers and linkers.
+//! - `ionice` is set by `ioprio_set(2)` on Linux.
+//! - `memory_max` limits the memory of the whole build by a cgroup v2 created under a delegated cgroup,
+//!   e.g. the systemd user session. It is ignored with a warning if no cgroup can be created.
+//!   Builds killed at the ceiling are retried with fewer jobs like other OOM-like failures.
+
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use std::{fmt, path::PathBuf, process, str::FromStr};
+
+use crate::error::*;
+
+/// Setting of priority and resource limits, `priority` table of config.toml
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct Priority {
+    /// Niceness of build processes, from -20 to 19
+    pub nice: Option<i32>,
+
+    /// I/O scheduling class and level of build processes
+    pub ionice: Option<IoPriority>,
+
+    /// Memory ceiling of the whole build
+    pub memory_max: Option<MemorySize>,
+}
+
+impl Priority {
+    /// Override by another setting, e.g. command line options
+    pub fn with_override(self, other: Priority) -> Self {
+        Priority {
+            nice: other.nice.or(self.nice),
+            ionice: other.ionice.or(self.ionice),
+            memory_max: other.memory_max.or(self.memory_max),
+        }
+    }
+
+    /// Prepare limits applied to commands, which creates a cgroup for `memory_max`
+    pub fn prepare(&self, name: &str) -> Limits {
+        let cgroup = self.memory_max.and_then(|max| {
+            let cgroup = cgroup::create(&format!("llvmmgmt-{name}"), max);
+            match &cgroup {
+                Some(cgroup) => info!("Limit memory to {} by cgroup: {}", max, cgroup.display()),
+                None => warn!(
+                    "cgroup v2 is not available, memory_max = {} is ignored",
+                    max
+                ),
+            }
+            cgroup
+        });
+        Limits {
+            priority: *self,
+            cgroup,
+        }
+    }
+}
+
+/// I/O priority, `idle` or `best-effort:{level}` where level is from 0 (highest) to 7 (lowest)
+///
+/// ```
+/// use llvmmgmt::priority::IoPriority;
+/// use std::str::FromStr;
+/// assert_eq!(IoPriority::from_str("idle").unwrap(), IoPriority::Idle);
+/// assert_eq!(IoPriority::from_str("best-effort:7").unwrap(), IoPriority::BestEffort(7));
+/// assert_eq!(IoPriority::from_str("best-effort").unwrap(), IoPriority::BestEffort(4));
+/// assert!(IoPriority::from_str("best-effort:8").is_err());
+/// assert!(IoPriority::from_str("realtime").is_err());
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub enum IoPriority {
+    Idle,
+    BestEffort(u8),
+}
+
+impl FromStr for IoPriority {
+    type Err = Error;
+    fn from_str(ionice: &str) -> Result<Self> {
+        let invalid = || Error::InvalidPriority {
+            name: ionice.into(),
+            message: "I/O priority must be `idle` or `best-effort:{0-7}`".into(),
+        };
+        let (class, level) = match ionice.split_once(':') {
+            Some((class, level)) => (class, Some(level)),
+            None => (ionice, None),
+        };
+        match (class, level) {
+            ("idle", None) => Ok(IoPriority::Idle),
+            ("best-effort", None) => Ok(IoPriority::BestEffort(4)),
+            ("best-effort", Some(level)) => match level.parse() {
+                Ok(level) if level <= 7 => Ok(IoPriority::BestEffort(level)),
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl TryFrom<String> for IoPriority {
+    type Error = Error;
+    fn try_from(ionice: String) -> Result<Self> {
+        IoPriority::from_str(&ionice)
+    }
+}
+
+impl From<IoPriority> for String {
+    fn from(ionice: IoPriority) -> Self {
+        ionice.to_string()
+    }
+}
+
+impl fmt::Display for IoPriority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IoPriority::Idle => write!(f, "idle"),
+            IoPriority::BestEffort(level) => write!(f, "best-effort:{level}"),
+        }
+    }
+}
+
+/// Memory size in bytes, written with a suffix `K`, `M`, `G`, or `T` in setting
+///
+/// ```
+/// use llvmmgmt::priority::MemorySize;
+/// use std::str::FromStr;
+/// assert_eq!(MemorySize::from_str("24G").unwrap(), MemorySize(24 << 30));
+/// assert_eq!(MemorySize::from_str("512m").unwrap().to_string(), "512M");
+/// assert_eq!(MemorySize::from_str("1000").unwrap(), MemorySize(1000));
+/// assert!(MemorySize::from_str("lots").is_err());
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct MemorySize(pub u64);
+
+const SIZE_SUFFIXES: [(char, u32); 4] = [('T', 40), ('G', 30), ('M', 20), ('K', 10)];
+
+impl FromStr for MemorySize {
+    type Err = Error;
+    fn from_str(size: &str) -> Result<Self> {
+        let upper = size.trim().to_ascii_uppercase();
+        let (digits, shift) = match SIZE_SUFFIXES
+            .iter()
+            .find(|(suffix, _)| upper.ends_with(*suffix))
+        {
+            Some((_, shift)) => (&upper[..upper.len() - 1], *shift),
+            None => (upper.as_str(), 0),
+        };
+        digits
+            .parse::<u64>()
+            .ok()
+            .and_then(|n| n.checked_mul(1 << shift))
+            .map(MemorySize)
+            .ok_or_else(|| Error::InvalidPriority {
+                name: size.into(),
+                message: "Memory size must be a number with optional K, M, G, or T suffix".into(),
+            })
+    }
+}
+
+impl TryFrom<String> for MemorySize {
+    type Error = Error;
+    fn try_from(size: String) -> Result<Self> {
+        MemorySize::from_str(&size)
+    }
+}
+
+impl From<MemorySize> for String {
+    fn from(size: MemorySize) -> Self {
+        size.to_string()
+    }
+}
+
+impl fmt::Display for MemorySize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (suffix, shift) in SIZE_SUFFIXES {
+            if self.0 != 0 && self.0.is_multiple_of(1 << shift) {
+                return write!(f, "{}{}", self.0 >> shift, suffix);
+            }
+        }
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Limits prepared for a build. The cgroup is removed when dropped.
+#[derive(Debug, Default)]
+pub struct Limits {
+    priority: Priority,
+    cgroup: Option<PathBuf>,
+}
+
+impl Limits {
+    /// Set up the command to run with the limits, see [CommandExt::limits](../error/trait.CommandExt.html)
+    pub fn apply<'a>(&self, command: &'a mut process::Command) -> &'a mut process::Command {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt as _;
+            let nice = self.priority.nice;
+            let ionice = self.priority.ionice;
+            let procs = self.cgroup.as_ref().and_then(|cgroup| {
+                use std::os::unix::ffi::OsStrExt;
+                std::ffi::CString::new(cgroup.join("cgroup.procs").as_os_str().as_bytes()).ok()
+            });
+            // Only async-signal-safe calls are allowed between fork and exec.
+            // Failures are ignored since the build runs fine without the limits.
+            unsafe {
+                command.pre_exec(move || {
+                    if let Some(nice) = nice {
+                        libc::setpriority(libc::PRIO_PROCESS as _, 0, nice);
+                    }
+                    #[cfg(target_os = "linux")]
+                    {
+                        if let Some(ionice) = ionice {
+                            libc::syscall(
+                                libc::SYS_ioprio_set,
+                                IOPRIO_WHO_PROCESS,
+                                0,
+                                ionice.value(),
+                            );
+                        }
+                        if let Some(procs) = &procs {
+                            let fd = libc::open(procs.as_ptr(), libc::O_WRONLY);
+                            if fd >= 0 {
+                                libc::write(fd, b"0".as_ptr() as *const _, 1);
+                                libc::close(fd);
+                            }
+                        }
+                    }
+                    #[cfg(not(target_os = "linux"))]
+                    let _ = (ionice, &procs);
+                    Ok(())
+                });
+            }
+        }
+        command
+    }
+}
+
+impl Drop for Limits {
+    fn drop(&mut self) {
+        if let Some(cgroup) = &self.cgroup {
+            // Fails if some processes are left, and it is cleaned up on the next build
+            let _ = std::fs::remove_dir(cgroup);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+
+impl IoPriority {
+    /// Value for `ioprio_set(2)`
+    #[cfg(target_os = "linux")]
+    fn value(&self) -> libc::c_int {
+        const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+        match self {
+            IoPriority::BestEffort(level) => (2 << IOPRIO_CLASS_SHIFT) | *level as libc::c_int,
+            IoPriority::Idle => 3 << IOPRIO_CLASS_SHIFT,
+        }
+    }
+}
+
+mod cgroup {
+    use super::MemorySize;
+    use std::{fs, path::*};
+
+    const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+    /// Create a cgroup with `memory.max` under the nearest ancestor which delegates the memory controller
+    pub fn create(name: &str, max: MemorySize) -> Option<PathBuf> {
+        let proc_cgroup = fs::read_to_string("/proc/self/cgroup").ok()?;
+        let current = current_cgroup(&proc_cgroup)?;
+        let mut dir = Path::new(CGROUP_ROOT).join(current.trim_start_matches('/'));
+        loop {
+            if has_memory_controller(&dir) {
+                let cgroup = dir.join(name);
+                if (cgroup.is_dir() || fs::create_dir(&cgroup).is_ok())
+                    && fs::write(cgroup.join("memory.max"), max.0.to_string()).is_ok()
+                {
+                    return Some(cgroup);
+                }
+            }
+            if dir == Path::new(CGROUP_ROOT) || !dir.pop() {
+                return None;
+            }
+        }
+    }
+
+    fn has_memory_controller(dir: &Path) -> bool {
+        fs::read_to_string(dir.join("cgroup.subtree_control"))
+            .map(|controllers| controllers.split_whitespace().any(|c| c == "memory"))
+            .unwrap_or(false)
+    }
+
+    /// Path of the cgroup v2 hierarchy in `/proc/self/cgroup`
+    pub(super) fn current_cgroup(proc_cgroup: &str) -> Option<&str> {
+        proc_cgroup
+            .lines()
+            .find_map(|line| line.strip_prefix("0::"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn current_cgroup() {
+        let proc_cgroup = "0::/user.slice/user-1000.slice/user@1000.service/app.slice/term.scope\n";
+        assert_eq!(
+            cgroup::current_cgroup(proc_cgroup),
+            Some("/user.slice/user-1000.slice/user@1000.service/app.slice/term.scope")
+        );
+        // cgroup v1 only
+        assert_eq!(cgroup::current_cgroup("4:memory:/user.slice\n"), None);
+    }
+
+    #[test]
+    fn override_priority() {
+        let config = Priority {
+            nice: Some(10),
+            ionice: Some(IoPriority::Idle),
+            memory_max: None,
+        };
+        let cli = Priority {
+            nice: Some(19),
+            memory_max: Some(MemorySize(1 << 30)),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.with_override(cli),
+            Priority {
+                nice: Some(19),
+                ionice: Some(IoPriority::Idle),
+                memory_max: Some(MemorySize(1 << 30)),
+            }
+        );
+    }
+
+    #[test]
+    fn priority_toml() -> Result<()> {
+        let priority: Priority =
+            toml::from_str("nice = 10\nionice = \"best-effort:7\"\nmemory_max = \"24G\"\n")?;
+        assert_eq!(priority.ionice, Some(IoPriority::BestEffort(7)));
+        assert_eq!(priority.memory_max, Some(MemorySize(24 << 30)));
+        assert_eq!(
+            toml::from_str::<Priority>(&toml::to_string(&priority)?)?,
+            priority
+        );
+        Ok(())
+    }
+}
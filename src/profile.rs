@@ -0,0 +1,139 @@
+//! Build profiles, i.e. named bundles of common LLVM build options
+//!
+//! Different from [variants](../variant/index.html), which are profiles installed under their own names,
+//! a profile does not change the name of the build.
+//! Profiles are referenced by `profile` of an entry, or given by `--profile` option of `install` and `entry build`,
+//! and applied on top of the entry setting in order:
+//!
+//! ```toml
+//! [my-local-llvm]
+//! path    = "/path/to/your/src"
+//! profile = ["dev"]
+//! ```
+//!
+//! Built-in profiles are
+//!
+//! - `dev`: assertions, shared libraries, split DWARF, and optimized TableGen for development of LLVM itself
+//! - `dist`: LLVM dylib linked by the tools, and stripped install for distribution
+//! - `minimal`: only the host target without tests, examples, benchmarks and docs
+//!
+//! and you can define your own profiles in `$XDG_CONFIG_HOME/llvmmgmt/config.toml`,
+//! which take precedence over the built-in ones:
+//!
+//! ```toml
+//! [profile.fast-dev]
+//! build_type = "Release"
+//! target     = ["X86"]
+//! option     = { LLVM_ENABLE_ASSERTIONS = true, LLVM_CCACHE_BUILD = true }
+//! ```
+
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{
+    cmake::CacheOptions,
+    config::*,
+    entry::{BuildType, EntrySetting},
+    error::*,
+};
+
+/// Option bundle layered on [EntrySetting](../entry/struct.EntrySetting.html), which is also the setting of a variant
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
+pub struct Profile {
+    /// Overrides `build_type` of the entry
+    pub build_type: Option<BuildType>,
+
+    /// Overrides `target` of the entry if not empty
+    #[serde(default)]
+    pub target: Vec<String>,
+
+    /// Additional LLVM build options, which overrides the options of the entry
+    #[serde(default)]
+    pub option: CacheOptions,
+
+    /// Overrides `strip` of the entry
+    pub strip: Option<bool>,
+}
+
+impl Profile {
+    pub(crate) fn with_options(options: &[(&str, bool)]) -> Self {
+        Profile {
+            option: options
+                .iter()
+                .map(|(key, value)| (key.to_string(), (*value).into()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Layer on an entry setting
+    pub fn apply_to(&self, setting: &mut EntrySetting) {
+        if let Some(build_type) = self.build_type {
+            setting.build_type = build_type;
+        }
+        if !self.target.is_empty() {
+            setting.target = self.target.clone();
+        }
+        setting
+            .option
+            .extend(self.option.iter().map(|(k, v)| (k.clone(), v.clone())));
+        if self.strip.is_some() {
+            setting.strip = self.strip;
+        }
+    }
+}
+
+/// Profiles shipped with llvmmgmt
+pub fn builtin_profiles() -> Vec<(&'static str, Profile)> {
+    let dev = Profile::with_options(&[
+        ("LLVM_ENABLE_ASSERTIONS", true),
+        ("BUILD_SHARED_LIBS", true),
+        ("LLVM_USE_SPLIT_DWARF", true),
+        ("LLVM_OPTIMIZED_TABLEGEN", true),
+    ]);
+    let dist = Profile {
+        build_type: Some(BuildType::Release),
+        strip: Some(true),
+        ..Profile::with_options(&[
+            ("LLVM_BUILD_LLVM_DYLIB", true),
+            ("LLVM_LINK_LLVM_DYLIB", true),
+            ("CLANG_LINK_CLANG_DYLIB", true),
+        ])
+    };
+    let minimal = Profile {
+        target: vec!["host".into()],
+        ..Profile::with_options(&[
+            ("LLVM_INCLUDE_TESTS", false),
+            ("LLVM_INCLUDE_EXAMPLES", false),
+            ("LLVM_INCLUDE_BENCHMARKS", false),
+            ("LLVM_INCLUDE_DOCS", false),
+            ("CLANG_INCLUDE_TESTS", false),
+            ("CLANG_INCLUDE_DOCS", false),
+        ])
+    };
+    vec![("dev", dev), ("dist", dist), ("minimal", minimal)]
+}
+
+/// Find a profile from config.toml, and then from the built-in profiles
+pub fn load_profile(name: &str) -> Result<Profile> {
+    find(name, &Config::global()?.profile, builtin_profiles()).ok_or_else(|| {
+        Error::InvalidProfile {
+            name: name.into(),
+            message: "Profile not found".into(),
+        }
+    })
+}
+
+/// Find a profile or variant defined by the user, and then a built-in one
+pub(crate) fn find(
+    name: &str,
+    defined: &HashMap<String, Profile>,
+    builtin: Vec<(&'static str, Profile)>,
+) -> Option<Profile> {
+    defined.get(name).cloned().or_else(|| {
+        builtin
+            .into_iter()
+            .find(|(builtin, _)| *builtin == name)
+            .map(|(_, profile)| profile)
+    })
+}
@@ -0,0 +1,258 @@
+//! Progress display of `cmake --build`
+//!
+//! Status lines of the build tool, `[42/4242] Building CXX object ...` of Ninja and `[ 42%] Building ...` of Makefile,
+//! are shown as a progress bar with the current target, elapsed time, and ETA.
+//! The full output is written into `llvmmgmt-build.log` in the build directory,
+//! or `llvmmgmt-test-<suite>.log` for test suites, and also to the terminal with `--verbose` option.
+//! When stdout is not a terminal, e.g. in CI, a line is printed for each percent instead of the bar.
+
+use indicatif::{ProgressBar, ProgressStyle};
+use lazy_static::lazy_static;
+use log::info;
+use regex::Regex;
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use crate::{bootstrap::format_duration, error::*};
+
+/// Log file of the build output in the build directory
+pub const BUILD_LOG: &str = "llvmmgmt-build.log";
+
+/// Name of the log of a test suite in the build directory, apart from the build log
+pub fn test_log(suite: &str) -> String {
+    format!("llvmmgmt-test-{suite}.log")
+}
+
+/// Number of the last output lines shown when the build fails
+const TAIL_LINES: usize = 40;
+
+/// Progress reported by a status line of the build tool
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status {
+    /// `[finished/total] description` of Ninja
+    Steps {
+        finished: u64,
+        total: u64,
+        description: String,
+    },
+    /// `[percent%] description` of Makefile
+    Percent { percent: u64, description: String },
+}
+
+lazy_static! {
+    static ref NINJA_STATUS: Regex = Regex::new(r"^\[(\d+)/(\d+)\]\s*(.*)$").unwrap();
+    static ref MAKE_STATUS: Regex = Regex::new(r"^\[\s*(\d+)%\]\s*(.*)$").unwrap();
+}
+
+impl Status {
+    /// Parse a status line of Ninja or Makefile
+    ///
+    /// ```
+    /// use llvmmgmt::progress::Status;
+    /// assert_eq!(
+    ///     Status::parse("[42/4242] Linking CXX executable bin/clang"),
+    ///     Some(Status::Steps { finished: 42, total: 4242, description: "Linking CXX executable bin/clang".into() })
+    /// );
+    /// assert_eq!(
+    ///     Status::parse("[  5%] Built target LLVMDemangle"),
+    ///     Some(Status::Percent { percent: 5, description: "Built target LLVMDemangle".into() })
+    /// );
+    /// assert_eq!(Status::parse("-- Installing: /usr/local/bin/clang"), None);
+    /// ```
+    pub fn parse(line: &str) -> Option<Self> {
+        if let Some(cap) = NINJA_STATUS.captures(line) {
+            return Some(Status::Steps {
+                finished: cap[1].parse().ok()?,
+                total: cap[2].parse().ok()?,
+                description: cap[3].into(),
+            });
+        }
+        let cap = MAKE_STATUS.captures(line)?;
+        Some(Status::Percent {
+            percent: cap[1].parse().ok()?,
+            description: cap[2].into(),
+        })
+    }
+
+    /// Progress in percent
+    pub fn percent(&self) -> u64 {
+        match self {
+            Status::Steps {
+                finished, total, ..
+            } => finished * 100 / (*total).max(1),
+            Status::Percent { percent, .. } => *percent,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match self {
+            Status::Steps { description, .. } | Status::Percent { description, .. } => description,
+        }
+    }
+}
+
+enum Display {
+    /// Progress bar on terminal
+    Bar(ProgressBar),
+    /// A line for each percent
+    Plain { last_percent: Option<u64> },
+    /// Print every line
+    Verbose,
+}
+
+/// Progress of a `cmake --build` of a target
+pub struct Progress {
+    target: String,
+    display: Display,
+    log: fs::File,
+    log_path: PathBuf,
+    tail: VecDeque<String>,
+    start: Instant,
+}
+
+impl Progress {
+    /// Start progress of a target, which appends the build output into the log
+    pub fn new(target: &str, log_path: &Path, verbose: bool) -> Result<Self> {
+        let log_path = log_path.to_path_buf();
+        let log = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)
+            .with(&log_path)?;
+        let display = if verbose {
+            Display::Verbose
+        } else if std::io::stdout().is_terminal() {
+            let bar = ProgressBar::new(100).with_style(
+                ProgressStyle::default_bar()
+                    .template("{spinner:.green} [{elapsed_precise}] [{bar:38.cyan/blue}] {pos}/{len} ({eta}) {wide_msg}")
+                    .progress_chars("#>-"),
+            );
+            bar.set_message(target);
+            Display::Bar(bar)
+        } else {
+            Display::Plain { last_percent: None }
+        };
+        Ok(Progress {
+            target: target.into(),
+            display,
+            log,
+            log_path,
+            tail: VecDeque::with_capacity(TAIL_LINES),
+            start: Instant::now(),
+        })
+    }
+
+    /// Handle a line of the build output
+    pub fn line(&mut self, line: &str) {
+        // Logging is best effort, and does not stop the build
+        let _ = writeln!(self.log, "{line}");
+        if self.tail.len() == TAIL_LINES {
+            self.tail.pop_front();
+        }
+        self.tail.push_back(line.into());
+
+        let status = Status::parse(line);
+        match (&mut self.display, status) {
+            (Display::Verbose, _) => println!("{line}"),
+            (Display::Bar(bar), Some(status)) => {
+                match &status {
+                    Status::Steps {
+                        finished, total, ..
+                    } => {
+                        bar.set_length(*total);
+                        bar.set_position(*finished);
+                    }
+                    Status::Percent { percent, .. } => {
+                        bar.set_length(100);
+                        bar.set_position(*percent);
+                    }
+                }
+                bar.set_message(status.description());
+            }
+            (Display::Plain { last_percent }, Some(status)) => {
+                let percent = status.percent();
+                if *last_percent != Some(percent) {
+                    *last_percent = Some(percent);
+                    println!(
+                        "{}: {:>3}% [{}] {}",
+                        self.target,
+                        percent,
+                        format_duration(self.start.elapsed()),
+                        status.description()
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Finish the progress, and show the last lines of the output if the build failed
+    pub fn finish<T>(self, result: &Result<T>) {
+        if let Display::Bar(bar) = &self.display {
+            if result.is_ok() {
+                bar.finish_and_clear();
+            } else {
+                bar.abandon();
+            }
+        }
+        match result {
+            Ok(_) => info!(
+                "Built {} in {}",
+                self.target,
+                format_duration(self.start.elapsed())
+            ),
+            Err(_) => {
+                if !matches!(self.display, Display::Verbose) {
+                    for line in &self.tail {
+                        eprintln!("{line}");
+                    }
+                }
+                eprintln!("Full build log: {}", self.log_path.display());
+            }
+        }
+    }
+}
+
+/// Truncate a log at the beginning of a build or a test run
+pub fn clear_log(log_path: &Path) -> Result<()> {
+    fs::File::create(log_path).with(log_path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_percent() {
+        let status = Status::parse("[2121/4242] Building CXX object lib/IR/Core.cpp.o").unwrap();
+        assert_eq!(status.percent(), 50);
+        let status = Status::parse("[100%] Built target clang").unwrap();
+        assert_eq!(status.percent(), 100);
+        assert_eq!(
+            Status::parse("[0/0] Re-running CMake...")
+                .unwrap()
+                .percent(),
+            0
+        );
+    }
+
+    #[test]
+    fn write_log() -> Result<()> {
+        let dir = tempfile::TempDir::new().with("/tmp")?;
+        let log_path = dir.path().join(BUILD_LOG);
+        clear_log(&log_path)?;
+        let mut progress = Progress::new("install", &log_path, false)?;
+        progress.line("[1/2] Building CXX object a.o");
+        progress.line("[2/2] Linking CXX executable a");
+        progress.finish(&Ok(()));
+        let log = fs::read_to_string(&log_path).with(&log_path)?;
+        assert_eq!(log.lines().count(), 2);
+        Ok(())
+    }
+}
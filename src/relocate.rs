@@ -0,0 +1,284 @@
+//! Relocation of a build moved from another prefix
+//!
+//! An install of LLVM may have its prefix baked into the CMake package files like
+//! `lib/cmake/llvm/LLVMConfig.cmake` and `lib/cmake/clang/ClangConfig.cmake`,
+//! into `llvm-config`, and into RPATHs of the binaries.
+//! A build archived on one machine and imported under another `$XDG_DATA_HOME`, user name, or `--as <name>`
+//! breaks downstream CMake projects unless these paths are rewritten.
+//!
+//! `llvmmgmt import` relocates the build from the prefix recorded in the manifest,
+//! or from a stale prefix found in the CMake package files:
+//!
+//! - Text files in `lib/cmake`, `lib/pkgconfig`, and scripts in `bin` are rewritten as is.
+//! - Strings in binaries, e.g. `llvm-config` and RPATHs, are rewritten in place padded by NUL,
+//!   which is possible only if the new prefix is not longer than the old one.
+//!   Otherwise the binary is reported as skipped.
+
+use log::info;
+use regex::Regex;
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{error::*, install};
+
+/// Files rewritten by relocation
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Relocation {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// Relative path of the file and the number of rewritten paths
+    pub changed: Vec<(String, usize)>,
+    /// Relative path of the file and the reason why it is not rewritten
+    pub skipped: Vec<(String, String)>,
+}
+
+impl fmt::Display for Relocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Relocated from {} to {}",
+            self.from.display(),
+            self.to.display()
+        )?;
+        for (path, count) in &self.changed {
+            writeln!(f, "  rewrote {}: {} paths", path, count)?;
+        }
+        for (path, reason) in &self.skipped {
+            writeln!(f, "  skipped {}: {}", path, reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether a byte can continue a path component, i.e. `from` is not a prefix of another path like `{from}-asserts`
+fn continues_path(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'+')
+}
+
+/// Positions of `from` not followed by a character of a path component
+fn find_prefix(data: &[u8], from: &[u8]) -> Vec<usize> {
+    let mut found = Vec::new();
+    let mut start = 0;
+    while start + from.len() <= data.len() {
+        match data[start..].windows(from.len()).position(|w| w == from) {
+            Some(pos) => {
+                let pos = start + pos;
+                let end = pos + from.len();
+                if end == data.len() || !continues_path(data[end]) {
+                    found.push(pos);
+                }
+                start = end;
+            }
+            None => break,
+        }
+    }
+    found
+}
+
+/// Replace the prefix in text
+///
+/// ```
+/// use llvmmgmt::relocate::replace_text;
+/// let (text, count) = replace_text("/old/18.1.8/lib;/old/18.1.8-asserts/lib", "/old/18.1.8", "/new/18.1.8");
+/// assert_eq!(text, "/new/18.1.8/lib;/old/18.1.8-asserts/lib");
+/// assert_eq!(count, 1);
+/// ```
+pub fn replace_text(text: &str, from: &str, to: &str) -> (String, usize) {
+    let found = find_prefix(text.as_bytes(), from.as_bytes());
+    let mut replaced = String::with_capacity(text.len());
+    let mut last = 0;
+    for &pos in &found {
+        replaced.push_str(&text[last..pos]);
+        replaced.push_str(to);
+        last = pos + from.len();
+    }
+    replaced.push_str(&text[last..]);
+    (replaced, found.len())
+}
+
+/// Replace the prefix in NUL-terminated strings of a binary in place, padding the strings by NUL.
+/// Returns the number of replaced strings, or `None` if `to` is longer than `from`.
+pub fn replace_binary(data: &mut [u8], from: &[u8], to: &[u8]) -> Option<usize> {
+    let found = find_prefix(data, from);
+    if found.is_empty() {
+        return Some(0);
+    }
+    if to.len() > from.len() {
+        return None;
+    }
+    for &pos in &found {
+        let end = data[pos..]
+            .iter()
+            .position(|&b| b == 0)
+            .map_or(data.len(), |n| pos + n);
+        let mut replaced = to.to_vec();
+        replaced.extend_from_slice(&data[pos + from.len()..end]);
+        replaced.resize(end - pos, 0);
+        data[pos..end].copy_from_slice(&replaced);
+    }
+    Some(found.len())
+}
+
+/// Text files which may contain the prefix
+fn text_files(prefix: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for dir in ["lib/cmake", "lib/pkgconfig", "bin"] {
+        collect_files(&prefix.join(dir), &mut files)?;
+    }
+    Ok(files)
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir).with(dir)? {
+        let path = entry.with(dir)?.path();
+        let meta = fs::symlink_metadata(&path).with(&path)?;
+        if meta.is_dir() {
+            collect_files(&path, files)?;
+        } else if meta.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Find a stale prefix of the build named `name` in the CMake package files under `root`,
+/// i.e. an absolute path other than `prefix` ending with the name followed by `/lib`, `/bin`, `/include`, or `/share`
+pub fn detect_prefix(root: &Path, name: &str, prefix: &Path) -> Result<Option<PathBuf>> {
+    let re = Regex::new(&format!(
+        r#"(/[^\s"';]*/{})/(?:lib|bin|include|share)\b"#,
+        regex::escape(name)
+    ))
+    .expect("valid regex");
+    let mut files = Vec::new();
+    collect_files(&root.join("lib/cmake"), &mut files)?;
+    for path in files {
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(_) => continue,
+        };
+        if let Some(found) = re
+            .captures_iter(&text)
+            .map(|c| PathBuf::from(&c[1]))
+            .find(|found| found != prefix)
+        {
+            return Ok(Some(found));
+        }
+    }
+    Ok(None)
+}
+
+/// Rewrite paths under `from` to `to` in the build at `root`,
+/// which can be a staging directory before the build is moved to `to`
+pub fn relocate(root: &Path, from: &Path, to: &Path) -> Result<Relocation> {
+    let mut relocation = Relocation {
+        from: from.into(),
+        to: to.into(),
+        ..Default::default()
+    };
+    let from_str = from.to_string_lossy();
+    let to_str = to.to_string_lossy();
+    let rel = |path: &Path| {
+        path.strip_prefix(root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned()
+    };
+
+    let binaries = install::installed_binaries(root)?;
+    for path in text_files(root)? {
+        if binaries.contains(&path) {
+            continue;
+        }
+        // Skip binaries of other formats
+        let text = match fs::read_to_string(&path) {
+            Ok(text) if !text.contains('\0') => text,
+            _ => continue,
+        };
+        let (replaced, count) = replace_text(&text, &from_str, &to_str);
+        if count > 0 {
+            fs::write(&path, replaced).with(&path)?;
+            relocation.changed.push((rel(&path), count));
+        }
+    }
+    for path in binaries {
+        let mut data = fs::read(&path).with(&path)?;
+        match replace_binary(&mut data, from_str.as_bytes(), to_str.as_bytes()) {
+            Some(0) => {}
+            Some(count) => {
+                fs::write(&path, data).with(&path)?;
+                relocation.changed.push((rel(&path), count));
+            }
+            None => relocation
+                .skipped
+                .push((rel(&path), "New prefix is longer than the old one".into())),
+        }
+    }
+    info!(
+        "Relocated {} files from {}",
+        relocation.changed.len(),
+        from.display()
+    );
+    Ok(relocation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binary_padding() {
+        let mut data =
+            b"\x7fELF\0/home/alice/llvm/18.1.8/lib:$ORIGIN\0/home/alice/llvm/18.1.80\0".to_vec();
+        let len = data.len();
+        assert_eq!(
+            replace_binary(&mut data, b"/home/alice/llvm/18.1.8", b"/opt/18.1.8"),
+            Some(1)
+        );
+        assert_eq!(data.len(), len);
+        assert_eq!(
+            &data[..],
+            &b"\x7fELF\0/opt/18.1.8/lib:$ORIGIN\0\0\0\0\0\0\0\0\0\0\0\0\0/home/alice/llvm/18.1.80\0"[..]
+        );
+        assert_eq!(
+            replace_binary(&mut data, b"/opt/18.1.8", b"/home/bob/llvm/18.1.8"),
+            None
+        );
+    }
+
+    #[test]
+    fn relocate_cmake_config() -> Result<()> {
+        let data_dir = tempfile::TempDir::new().with("/tmp")?;
+        let prefix = data_dir.path().join("llvm-18");
+        let cmake = prefix.join("lib/cmake/llvm");
+        fs::create_dir_all(&cmake).with(&cmake)?;
+        let config = cmake.join("LLVMConfig.cmake");
+        fs::write(
+            &config,
+            "set(LLVM_INSTALL_PREFIX \"/home/alice/.local/share/llvmmgmt/18.1.8\")\n\
+             set(LLVM_CMAKE_DIR \"/home/alice/.local/share/llvmmgmt/18.1.8/lib/cmake/llvm\")\n",
+        )
+        .with(&config)?;
+
+        let from = detect_prefix(&prefix, "18.1.8", &prefix)?.unwrap();
+        assert_eq!(from, Path::new("/home/alice/.local/share/llvmmgmt/18.1.8"));
+        let relocation = relocate(&prefix, &from, &prefix)?;
+        assert_eq!(
+            relocation.changed,
+            [("lib/cmake/llvm/LLVMConfig.cmake".to_string(), 2)]
+        );
+        assert_eq!(
+            fs::read_to_string(&config).with(&config)?,
+            format!(
+                "set(LLVM_INSTALL_PREFIX \"{0}\")\nset(LLVM_CMAKE_DIR \"{0}/lib/cmake/llvm\")\n",
+                prefix.display()
+            )
+        );
+        assert_eq!(detect_prefix(&prefix, "18.1.8", &prefix)?, None);
+        Ok(())
+    }
+}
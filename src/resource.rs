@@ -6,11 +6,15 @@ use futures::{
 };
 use indicatif::{ProgressBar, ProgressStyle};
 use log::*;
+use sha2::{Digest, Sha256};
 use std::{fs, io, path::*, process::Command};
 use tempfile::TempDir;
 use url::Url;
 
-use crate::error::*;
+use crate::{archive::HashReader, error::*};
+
+/// SHA-256 of a downloaded tarball, recorded in the directory where it is expanded
+pub const TARBALL_SHA256: &str = ".llvmmgmt-tarball.sha256";
 
 /// Remote LLVM/Clang resource
 #[derive(Debug, PartialEq)]
@@ -52,62 +56,11 @@ impl Resource {
     /// let tar = Resource::from_url(tar_url).unwrap();
     /// assert_eq!(tar, Resource::Tar { url: tar_url.into() });
     /// ```
+    ///
+    /// Other URLs are tried with `git ls-remote`, and regarded as SVN repositories if it fails.
     pub fn from_url(url_str: &str) -> Result<Self> {
-        // Check file extension
-        if let Ok(filename) = get_filename_from_url(url_str) {
-            for ext in &[".tar.gz", ".tar.xz", ".tar.bz2", ".tar.Z", ".tgz", ".taz"] {
-                if filename.ends_with(ext) {
-                    debug!("Find archive extension '{ext}' at the end of URL");
-                    return Ok(Resource::Tar {
-                        url: url_str.into(),
-                    });
-                }
-            }
-
-            if filename.ends_with("trunk") {
-                debug!("Find 'trunk' at the end of URL");
-                return Ok(Resource::Svn {
-                    url: url_str.into(),
-                });
-            }
-
-            if filename.ends_with(".git") {
-                debug!("Find '.git' extension");
-                return Ok(Resource::Git {
-                    url: strip_branch_from_url(url_str)?,
-                    branch: get_branch_from_url(url_str)?,
-                });
-            }
-        }
-
-        // Hostname
-        let url = Url::parse(url_str).map_err(|_| Error::InvalidUrl {
-            url: url_str.into(),
-        })?;
-        for service in &["github.com", "gitlab.com"] {
-            if url.host_str() == Some(service) {
-                debug!("URL is a cloud git service: {service}");
-                return Ok(Resource::Git {
-                    url: strip_branch_from_url(url_str)?,
-                    branch: get_branch_from_url(url_str)?,
-                });
-            }
-        }
-
-        if url.host_str() == Some("llvm.org") {
-            if url.path().starts_with("/svn") {
-                debug!("URL is LLVM SVN repository");
-                return Ok(Resource::Svn {
-                    url: url_str.into(),
-                });
-            }
-            if url.path().starts_with("/git") {
-                debug!("URL is LLVM Git repository");
-                return Ok(Resource::Git {
-                    url: strip_branch_from_url(url_str)?,
-                    branch: get_branch_from_url(url_str)?,
-                });
-            }
+        if let Some(resource) = Self::from_url_offline(url_str)? {
+            return Ok(resource);
         }
 
         // Try access with git
@@ -155,6 +108,77 @@ impl Resource {
         }
     }
 
+    /// [from_url](#method.from_url) without accessing the network.
+    /// `None` if the URL does not tell Git or SVN, which only `git ls-remote` can detect.
+    ///
+    /// ```
+    /// # use llvmmgmt::resource::Resource;
+    /// let github_mirror = "https://github.com/llvm/llvm-project";
+    /// let git = Resource::from_url_offline(github_mirror).unwrap();
+    /// assert_eq!(git, Some(Resource::Git { url: github_mirror.into(), branch: None }));
+    /// let unknown = Resource::from_url_offline("https://example.com/llvm").unwrap();
+    /// assert_eq!(unknown, None);
+    /// ```
+    pub fn from_url_offline(url_str: &str) -> Result<Option<Self>> {
+        // Check file extension
+        if let Ok(filename) = get_filename_from_url(url_str) {
+            for ext in &[".tar.gz", ".tar.xz", ".tar.bz2", ".tar.Z", ".tgz", ".taz"] {
+                if filename.ends_with(ext) {
+                    debug!("Find archive extension '{ext}' at the end of URL");
+                    return Ok(Some(Resource::Tar {
+                        url: url_str.into(),
+                    }));
+                }
+            }
+
+            if filename.ends_with("trunk") {
+                debug!("Find 'trunk' at the end of URL");
+                return Ok(Some(Resource::Svn {
+                    url: url_str.into(),
+                }));
+            }
+
+            if filename.ends_with(".git") {
+                debug!("Find '.git' extension");
+                return Ok(Some(Resource::Git {
+                    url: strip_branch_from_url(url_str)?,
+                    branch: get_branch_from_url(url_str)?,
+                }));
+            }
+        }
+
+        // Hostname
+        let url = Url::parse(url_str).map_err(|_| Error::InvalidUrl {
+            url: url_str.into(),
+        })?;
+        for service in &["github.com", "gitlab.com"] {
+            if url.host_str() == Some(service) {
+                debug!("URL is a cloud git service: {service}");
+                return Ok(Some(Resource::Git {
+                    url: strip_branch_from_url(url_str)?,
+                    branch: get_branch_from_url(url_str)?,
+                }));
+            }
+        }
+
+        if url.host_str() == Some("llvm.org") {
+            if url.path().starts_with("/svn") {
+                debug!("URL is LLVM SVN repository");
+                return Ok(Some(Resource::Svn {
+                    url: url_str.into(),
+                }));
+            }
+            if url.path().starts_with("/git") {
+                debug!("URL is LLVM Git repository");
+                return Ok(Some(Resource::Git {
+                    url: strip_branch_from_url(url_str)?,
+                    branch: get_branch_from_url(url_str)?,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
     pub fn download(&self, dest: &Path) -> Result<()> {
         if !dest.exists() {
             fs::create_dir_all(dest).with(dest)?;
@@ -181,7 +205,7 @@ impl Resource {
                 info!("Download Tar file: {url}");
                 // This will be large, but at most ~100MB
                 let rt = tokio::runtime::Runtime::new()?;
-                let mut bytes = rt.block_on(download(url))?;
+                let mut bytes = HashReader::new(rt.block_on(download(url))?);
                 let xz_buf = xz2::read::XzDecoder::new(&mut bytes);
                 let mut tar_buf = tar::Archive::new(xz_buf);
                 let entries = tar_buf
@@ -202,6 +226,10 @@ impl Resource {
                         }
                     }
                 }
+                drop(tar_buf);
+                io::copy(&mut bytes, &mut io::sink()).with(dest)?;
+                let sha256 = dest.join(TARBALL_SHA256);
+                fs::write(&sha256, format!("{}\n", bytes.checksum())).with(&sha256)?;
             }
         }
         Ok(())
@@ -221,6 +249,109 @@ impl Resource {
         }
         Ok(())
     }
+
+    /// Revision checked out in `dest`, or the revision at the head of the remote if not checked out.
+    /// Tar archives have no revision, and are identified by the URL.
+    pub fn revision(&self, dest: &Path) -> Result<Option<String>> {
+        match self.checkout_revision(dest)? {
+            Some(revision) => Ok(Some(revision)),
+            None => self.remote_revision(),
+        }
+    }
+
+    /// Revision checked out in `dest`, i.e. commit SHA for Git, revision number for SVN,
+    /// and SHA-256 of the tarball for Tar
+    pub fn checkout_revision(&self, dest: &Path) -> Result<Option<String>> {
+        match self {
+            Resource::Svn { .. } if dest.join(".svn").exists() => {
+                let (stdout, _) = Command::new("svn")
+                    .args(["info", "--show-item", "revision"])
+                    .current_dir(dest)
+                    .check_output()?;
+                Ok(Some(stdout.trim().to_string()))
+            }
+            Resource::Git { .. } => match git_toplevel(dest) {
+                Some(toplevel) => git_revision(&toplevel).map(Some),
+                None => Ok(None),
+            },
+            Resource::Tar { .. } if dest.join(TARBALL_SHA256).exists() => {
+                let path = dest.join(TARBALL_SHA256);
+                Ok(Some(
+                    fs::read_to_string(&path).with(&path)?.trim().to_string(),
+                ))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Revision at the head of the remote branch
+    pub fn remote_revision(&self) -> Result<Option<String>> {
+        match self {
+            Resource::Svn { url } => {
+                let (stdout, _) = Command::new("svn")
+                    .args(["info", "--show-item", "revision", url.as_str()])
+                    .check_output()?;
+                Ok(Some(stdout.trim().to_string()))
+            }
+            Resource::Git { url, branch } => {
+                let head = match branch {
+                    Some(branch) => format!("refs/heads/{branch}"),
+                    None => "HEAD".into(),
+                };
+                let (stdout, _) = Command::new("git")
+                    .args(["ls-remote", url.as_str(), &head])
+                    .check_output()?;
+                match stdout.split_whitespace().next() {
+                    Some(sha) => Ok(Some(sha.to_string())),
+                    None => Err(Error::InvalidUrl {
+                        url: format!("{url}#{head}"),
+                    }),
+                }
+            }
+            Resource::Tar { .. } => Ok(None),
+        }
+    }
+}
+
+/// Top-level directory of the Git work tree containing `dest`, which may be a subdirectory of the repository
+fn git_toplevel(dest: &Path) -> Option<PathBuf> {
+    if !dest.is_dir() {
+        return None;
+    }
+    let (stdout, _) = Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .current_dir(dest)
+        .check_output()
+        .ok()?;
+    Some(PathBuf::from(stdout.trim()))
+}
+
+/// Commit SHA of HEAD in a Git working tree. Uncommitted changes are identified by `-dirty-`
+/// and the SHA-256 of `git status --porcelain`, `git diff HEAD`, and the untracked files following the SHA.
+fn git_revision(dest: &Path) -> Result<String> {
+    let git = |args: &[&str]| -> Result<String> {
+        let (stdout, _) = Command::new("git")
+            .args(args)
+            .current_dir(dest)
+            .check_output()?;
+        Ok(stdout)
+    };
+    let head = git(&["rev-parse", "HEAD"])?.trim().to_string();
+    let status = git(&["status", "--porcelain"])?;
+    if status.is_empty() {
+        return Ok(head);
+    }
+    let diff = git(&["diff", "--binary", "HEAD"])?;
+    let mut changes = Sha256::new().chain_update(status).chain_update(diff);
+    // `git status` lists the untracked files, but not their contents
+    for file in git(&["ls-files", "-z", "--others", "--exclude-standard"])?.split_terminator('\0') {
+        let path = dest.join(file);
+        if path.is_file() {
+            changes.update(file);
+            changes.update(fs::read(&path).with(&path)?);
+        }
+    }
+    Ok(format!("{}-dirty-{:.16x}", head, changes.finalize()))
 }
 
 struct Download<T> {
@@ -333,6 +464,98 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_git_revision() -> Result<()> {
+        let repo = TempDir::new().with("/tmp")?;
+        let git = |args: &[&str]| {
+            Command::new("git")
+                .args([
+                    "-c",
+                    "user.name=llvmmgmt",
+                    "-c",
+                    "user.email=llvmmgmt@localhost",
+                ])
+                .args(args)
+                .current_dir(repo.path())
+                .check_output()
+        };
+        git(&["init", "-q", "-b", "main"])?;
+        git(&["commit", "-q", "--allow-empty", "-m", "init"])?;
+        let (sha, _) = git(&["rev-parse", "HEAD"])?;
+
+        let url = repo.path().to_string_lossy().into_owned();
+        let head = Resource::Git {
+            url: url.clone(),
+            branch: None,
+        };
+        assert_eq!(head.remote_revision()?.as_deref(), Some(sha.trim()));
+        assert_eq!(
+            head.checkout_revision(repo.path())?.as_deref(),
+            Some(sha.trim())
+        );
+        let branch = Resource::Git {
+            url,
+            branch: Some("main".into()),
+        };
+        // Not checked out
+        let empty = TempDir::new().with("/tmp")?;
+        assert_eq!(branch.checkout_revision(empty.path())?, None);
+        assert_eq!(branch.revision(empty.path())?.as_deref(), Some(sha.trim()));
+
+        // Uncommitted changes
+        fs::write(repo.path().join("a.txt"), "a").with(repo.path())?;
+        let untracked = head.checkout_revision(repo.path())?.unwrap();
+        assert!(untracked.starts_with(&format!("{}-dirty-", sha.trim())));
+        fs::write(repo.path().join("a.txt"), "c").with(repo.path())?;
+        assert_ne!(head.checkout_revision(repo.path())?.unwrap(), untracked);
+        fs::write(repo.path().join("a.txt"), "a").with(repo.path())?;
+        git(&["add", "a.txt"])?;
+        let added = head.checkout_revision(repo.path())?.unwrap();
+        assert_ne!(added, untracked);
+        fs::write(repo.path().join("a.txt"), "b").with(repo.path())?;
+        git(&["add", "a.txt"])?;
+        assert_ne!(head.checkout_revision(repo.path())?.unwrap(), added);
+        Ok(())
+    }
+
+    #[test]
+    fn test_git_revision_subdirectory() -> Result<()> {
+        let repo = TempDir::new().with("/tmp")?;
+        let git = |args: &[&str]| {
+            Command::new("git")
+                .args([
+                    "-c",
+                    "user.name=llvmmgmt",
+                    "-c",
+                    "user.email=llvmmgmt@localhost",
+                ])
+                .args(args)
+                .current_dir(repo.path())
+                .check_output()
+        };
+        let llvm = repo.path().join("llvm");
+        fs::create_dir(&llvm).with(&llvm)?;
+        fs::write(llvm.join("CMakeLists.txt"), "").with(&llvm)?;
+        git(&["init", "-q", "-b", "main"])?;
+        git(&["add", "llvm"])?;
+        git(&["commit", "-q", "-m", "init"])?;
+        let (sha, _) = git(&["rev-parse", "HEAD"])?;
+
+        // Local source like `llvm-project/llvm`
+        let local = Resource::Git {
+            url: llvm.to_string_lossy().into_owned(),
+            branch: None,
+        };
+        assert_eq!(local.checkout_revision(&llvm)?.as_deref(), Some(sha.trim()));
+        // Changes of other projects in the monorepo are built together
+        let clang = repo.path().join("clang");
+        fs::create_dir(&clang).with(&clang)?;
+        fs::write(clang.join("CMakeLists.txt"), "").with(&clang)?;
+        let dirty = local.checkout_revision(&llvm)?.unwrap();
+        assert!(dirty.starts_with(&format!("{}-dirty-", sha.trim())));
+        Ok(())
+    }
+
     #[test]
     fn test_get_filename_from_url() {
         let url = "http://releases.llvm.org/6.0.1/llvm-6.0.1.src.tar.xz";
@@ -0,0 +1,297 @@
+//! Timing and statistics of builds
+//!
+//! After a build, the wall time of each phase (`checkout`, `configure`, `build`, `install`,
+//! or the stages of a [bootstrap](../bootstrap/index.html) build), the peak disk usage of the build directory,
+//! and the slowest targets in `.ninja_log` are recorded with the build, and shown by `llvmmgmt stats`:
+//!
+//! ```shell
+//! $ llvmmgmt stats 18.1.8
+//! Build 18.1.8 (Release, 16 compile and 4 link jobs)
+//!     checkout: 1m02s
+//!    configure: 32s
+//!        build: 1h02m03s
+//!      install: 45s
+//!        total: 1h04m22s
+//! Peak disk usage of build directory: 4.2 GiB
+//! Slowest targets:
+//!     3m12s bin/clang-18
+//!     ...
+//! ```
+//!
+//! The disk usage is sampled at the end of each phase, and temporary files removed within a phase are not counted.
+//!
+//! The duration of a new build is estimated from the recorded builds of the same entry,
+//! or of other entries with the same build type.
+
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, fs, path::Path, time::Duration};
+
+use crate::{
+    bootstrap::format_duration,
+    build::builds,
+    entry::{BuildType, Entry},
+    error::*,
+    jobs::Jobs,
+};
+
+/// Number of the slowest targets recorded
+const SLOWEST_TARGETS: usize = 10;
+
+/// Statistics of a build, recorded in [BuildInfo](../build/struct.BuildInfo.html)
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
+pub struct BuildStats {
+    pub build_type: BuildType,
+
+    /// Peak disk usage of the build directory in bytes, sampled at the end of each phase
+    pub peak_disk_usage: u64,
+
+    /// Jobs at the end of the build
+    pub jobs: Option<Jobs>,
+
+    /// Wall time of each phase in build order
+    #[serde(default)]
+    pub phases: Vec<Timing>,
+
+    /// Slowest targets in `.ninja_log`
+    #[serde(default)]
+    pub slowest_targets: Vec<Timing>,
+}
+
+/// Wall time of a phase or target
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Timing {
+    pub name: String,
+    pub seconds: f64,
+}
+
+impl Timing {
+    fn new(name: &str, duration: Duration) -> Self {
+        Timing {
+            name: name.into(),
+            seconds: duration.as_secs_f64(),
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.seconds)
+    }
+}
+
+impl BuildStats {
+    /// Collect statistics after a build
+    pub fn new(
+        build_type: BuildType,
+        jobs: Jobs,
+        phases: &[(&'static str, Duration)],
+        peak_disk_usage: u64,
+        build_dir: &Path,
+    ) -> Self {
+        let slowest_targets = fs::read_to_string(build_dir.join(".ninja_log"))
+            .map(|log| slowest_targets(&log, SLOWEST_TARGETS))
+            .unwrap_or_default();
+        BuildStats {
+            build_type,
+            peak_disk_usage,
+            jobs: Some(jobs),
+            phases: phases
+                .iter()
+                .map(|(name, duration)| Timing::new(name, *duration))
+                .collect(),
+            slowest_targets,
+        }
+    }
+
+    /// Total wall time of all phases
+    pub fn total(&self) -> Duration {
+        self.phases.iter().map(Timing::duration).sum()
+    }
+}
+
+impl fmt::Display for BuildStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for phase in &self.phases {
+            writeln!(
+                f,
+                "{:>12}: {}",
+                phase.name,
+                format_duration(phase.duration())
+            )?;
+        }
+        writeln!(f, "{:>12}: {}", "total", format_duration(self.total()))?;
+        writeln!(
+            f,
+            "Peak disk usage of build directory: {}",
+            format_size(self.peak_disk_usage)
+        )?;
+        if !self.slowest_targets.is_empty() {
+            writeln!(f, "Slowest targets:")?;
+            for target in &self.slowest_targets {
+                writeln!(
+                    f,
+                    "{:>12} {}",
+                    format_duration(target.duration()),
+                    target.name
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Slowest targets in `.ninja_log` (format v5: `start end mtime output hash` in milliseconds)
+fn slowest_targets(ninja_log: &str, n: usize) -> Vec<Timing> {
+    // Outputs rebuilt in the later builds appear again, and the last one is valid
+    let mut durations = HashMap::new();
+    for line in ninja_log.lines().filter(|line| !line.starts_with('#')) {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 4 {
+            continue;
+        }
+        if let (Ok(start), Ok(end)) = (fields[0].parse::<u64>(), fields[1].parse::<u64>()) {
+            durations.insert(fields[3], end.saturating_sub(start));
+        }
+    }
+    let mut targets: Vec<_> = durations.into_iter().collect();
+    targets.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    targets
+        .into_iter()
+        .take(n)
+        .map(|(name, ms)| Timing::new(name, Duration::from_millis(ms)))
+        .collect()
+}
+
+/// Disk usage of a file or directory in bytes
+pub fn disk_usage(path: &Path) -> u64 {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(_) => return 0,
+    };
+    let mut size = allocated_size(&meta);
+    if meta.is_dir() {
+        if let Ok(entries) = fs::read_dir(path) {
+            size += entries
+                .flatten()
+                .map(|entry| disk_usage(&entry.path()))
+                .sum::<u64>();
+        }
+    }
+    size
+}
+
+#[cfg(unix)]
+fn allocated_size(meta: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    meta.blocks() * 512
+}
+
+#[cfg(not(unix))]
+fn allocated_size(meta: &fs::Metadata) -> u64 {
+    meta.len()
+}
+
+/// Format bytes in binary units
+///
+/// ```
+/// use llvmmgmt::stats::format_size;
+/// assert_eq!(format_size(512), "512 B");
+/// assert_eq!(format_size(3 << 29), "1.5 GiB");
+/// ```
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Estimate the duration of building an entry from the recorded builds,
+/// preferring the builds of the same entry and then of the same build type.
+/// Returns the name of the build used for the estimation.
+pub fn estimate(entry: &Entry, jobs: Jobs) -> Result<Option<(String, Duration)>> {
+    let build_type = entry.setting().build_type;
+    let mut best: Option<(u8, String, BuildStats)> = None;
+    for build in builds()? {
+        let info = match build.info()? {
+            Some(info) => info,
+            None => continue,
+        };
+        let stats = match info.stats {
+            Some(stats) => stats,
+            None => continue,
+        };
+        let score = match (info.entry == entry.name(), stats.build_type == build_type) {
+            (true, true) => 3,
+            (true, false) => 2,
+            (false, true) => 1,
+            (false, false) => continue,
+        };
+        if best.as_ref().is_none_or(|(best, ..)| score > *best) {
+            best = Some((score, build.name().into(), stats));
+        }
+    }
+    Ok(best.map(|(_, name, stats)| {
+        let mut total = stats.total().as_secs_f64();
+        // Roughly scales by the number of compile jobs
+        if let Some(prev) = stats.jobs {
+            total *= prev.compile as f64 / jobs.compile as f64;
+        }
+        (name, Duration::from_secs_f64(total))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::BuildInfo;
+
+    #[test]
+    fn ninja_log() {
+        let log = "# ninja log v5\n\
+                   0\t1000\t0\tlib/a.o\tdeadbeef\n\
+                   0\t5000\t0\tbin/clang\tdeadbeef\n\
+                   100\t300\t0\tlib/b.o\tdeadbeef\n\
+                   2000\t2500\t0\tlib/a.o\tdeadbeef\n";
+        let targets = slowest_targets(log, 2);
+        assert_eq!(
+            targets,
+            vec![
+                Timing::new("bin/clang", Duration::from_secs(5)),
+                Timing::new("lib/a.o", Duration::from_millis(500)),
+            ]
+        );
+    }
+
+    #[test]
+    fn stats_with_build_info() -> Result<()> {
+        let stats = BuildStats::new(
+            BuildType::Debug,
+            Jobs {
+                compile: 8,
+                link: 1,
+            },
+            &[
+                ("configure", Duration::from_secs(30)),
+                ("build", Duration::from_secs(3600)),
+            ],
+            1 << 30,
+            Path::new("/nonexistent"),
+        );
+        assert_eq!(stats.total(), Duration::from_secs(3630));
+
+        let prefix = tempfile::TempDir::new().with("/tmp")?;
+        let info = BuildInfo {
+            entry: "18.1.8".into(),
+            stats: Some(stats),
+            ..Default::default()
+        };
+        info.save(prefix.path())?;
+        assert_eq!(BuildInfo::load(prefix.path())?, Some(info));
+        Ok(())
+    }
+}
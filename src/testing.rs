@@ -0,0 +1,49 @@
+//! Helpers shared by unit tests
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use tempfile::TempDir;
+
+use crate::{
+    build::Build,
+    config::{APP_NAME, ENTRY_TOML},
+    error::*,
+};
+
+thread_local! {
+    static HOME: TempDir = {
+        let home = TempDir::new().expect("temporary directory");
+        let config = home.path().join("config").join(APP_NAME);
+        fs::create_dir_all(&config).expect("config directory");
+        fs::write(config.join(ENTRY_TOML), "").expect("empty entry.toml");
+        home
+    };
+}
+
+/// Directory replacing the config, data, and cache directories of the user in unit tests,
+/// so that tests do not depend on `config.toml`, `entry.toml` and the builds of the user.
+/// Each test thread has its own directory with an empty `entry.toml`, removed when the test finishes
+pub fn home() -> PathBuf {
+    HOME.with(|home| home.path().to_path_buf())
+}
+
+/// Build named `name` in `data_dir` with `bin/clang`, `bin/clang++` linked to it,
+/// and `lib/cmake/llvm/LLVMConfig.cmake` embedding the prefix
+pub fn fake_build(data_dir: &Path, name: &str) -> Result<Build> {
+    let prefix = data_dir.join(name);
+    let bin = prefix.join("bin");
+    fs::create_dir_all(&bin).with(&bin)?;
+    fs::write(bin.join("clang"), "#!/bin/sh\n").with(&bin)?;
+    #[cfg(unix)]
+    std::os::unix::fs::symlink("clang", bin.join("clang++")).with(&bin)?;
+    let lib = prefix.join("lib/cmake/llvm");
+    fs::create_dir_all(&lib).with(&lib)?;
+    fs::write(
+        lib.join("LLVMConfig.cmake"),
+        format!("set(LLVM_CMAKE_DIR {}/lib/cmake/llvm)\n", prefix.display()),
+    )
+    .with(&lib)?;
+    Ok(Build::from_path(&prefix))
+}
@@ -0,0 +1,711 @@
+//! Host toolchain used to compile LLVM/Clang
+//!
+//! `compiler` property of an entry selects the C/C++ compilers for CMake
+//! (`CMAKE_C_COMPILER` and `CMAKE_CXX_COMPILER`), which is useful for old releases
+//! which cannot be built by a modern compiler:
+//!
+//! ```toml
+//! [3.9.1]
+//! url = "http://releases.llvm.org/3.9.1/llvm-3.9.1.src.tar.xz"
+//! compiler = "gcc-7"            # or "/opt/gcc-7/bin/gcc", or "build:6.0.1" for clang of an installed build
+//! ```
+//!
+//! The C++ compiler is found next to the C compiler, e.g. `g++-7` for `gcc-7`.
+//!
+//! Cross compile
+//! --------------
+//! `cross` table builds LLVM/Clang running on another host, e.g. AArch64 or RISC-V on x86_64:
+//!
+//! ```toml
+//! [18.1.8-aarch64]
+//! url = "https://github.com/llvm/llvm-project/releases/download/llvmorg-18.1.8/llvm-18.1.8.src.tar.xz"
+//!
+//! [18.1.8-aarch64.cross]
+//! host_triple = "aarch64-linux-gnu"
+//! sysroot     = "/usr/aarch64-linux-gnu"
+//! compiler    = "aarch64-linux-gnu-gcc"   # clang with --target=host_triple if omitted
+//! # native_tool_dir = "~/.local/share/llvmmgmt/18.1.8/bin"
+//! ```
+//!
+//! A CMake toolchain file is generated in the build directory.
+//! TableGen has to run on the build machine, so `llvm-tblgen` and `clang-tblgen` in `native_tool_dir` are used,
+//! or they are built for the build machine in `{build dir}/native` before the cross build.
+//! `llvm-tblgen` is required in `native_tool_dir`, while `clang-tblgen` is used only if it exists.
+//! The host compiler is set only by `cross.compiler`, and `compiler` of the entry is rejected with `cross`.
+//! It is checked by compiling a test program for the host before configure.
+//! The installed build is flagged as not runnable on the current host.
+//!
+//! Compiler launcher and linker
+//! -----------------------------
+//! `launcher` (`none`, `ccache`, `sccache`, `distcc` or `icecc`) and
+//! `linker` (`default`, `lld`, `gold`, `mold` or a path to a linker) properties of an entry
+//! select them explicitly. Defaults for all entries can be set in config.toml:
+//!
+//! ```toml
+//! launcher = "ccache"
+//! linker   = "lld"
+//! ```
+//!
+//! They are checked to be available before configure, and recorded with the build.
+//! Commands are searched in `PATH` of the build processes, which may be set by `env` of the entry
+//! (see [environment](../environment/index.html) module).
+
+use log::info;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+};
+use tempfile::TempDir;
+
+use crate::{build::Build, config::*, entry::Entry, environment::BuildEnv, error::*};
+
+const HELLO_C: &str = "#include <stdio.h>\nint main(void) { printf(\"hello\\n\"); return 0; }\n";
+const HELLO_CXX: &str =
+    "#include <iostream>\n#include <vector>\nint main() { std::vector<int> v{1}; std::cout << v[0] << std::endl; }\n";
+
+/// Pair of C and C++ compilers
+#[derive(Debug, Clone, PartialEq)]
+pub struct Compiler {
+    pub cc: PathBuf,
+    pub cxx: PathBuf,
+}
+
+impl Compiler {
+    /// Resolve `compiler` property: a path, a command name in `PATH` of the build, or `build:{name}`
+    pub fn resolve(spec: &str, env: &BuildEnv) -> Result<Self> {
+        if let Some(name) = spec.strip_prefix("build:") {
+            let build = Build::from_name(name)?;
+            if !build.exists() {
+                return Err(invalid(spec, "llvmmgmt build does not exist"));
+            }
+            return Ok(Self::from_build(&build));
+        }
+        let cc_name = Path::new(spec)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| invalid(spec, "not a file name"))?;
+        let cxx_name =
+            cxx_name(cc_name).ok_or_else(|| invalid(spec, "C++ compiler cannot be guessed"))?;
+        let (cc, cxx) = if spec.contains('/') {
+            let cc = expand_path(spec);
+            let cxx = cc.with_file_name(cxx_name);
+            (cc, cxx)
+        } else {
+            (
+                env.which(spec)
+                    .ok_or_else(|| invalid(spec, "not found in PATH"))?,
+                env.which(&cxx_name)
+                    .ok_or_else(|| invalid(&cxx_name, "not found in PATH"))?,
+            )
+        };
+        for path in [&cc, &cxx] {
+            if !path.is_file() {
+                return Err(invalid(&path.display().to_string(), "not found"));
+            }
+        }
+        Ok(Compiler { cc, cxx })
+    }
+
+    /// clang and clang++ of an llvmmgmt build
+    pub fn from_build(build: &Build) -> Self {
+        let bin = build.prefix().join("bin");
+        Compiler {
+            cc: bin.join("clang"),
+            cxx: bin.join("clang++"),
+        }
+    }
+
+    /// Check that the compilers work by compiling and linking a small program in the environment of the build
+    pub fn check(&self, env: &BuildEnv) -> Result<()> {
+        self.check_with(env, &[])
+    }
+
+    /// Check the compilers with additional arguments, e.g. `-c` and `--target` of a cross compiler
+    fn check_with(&self, env: &BuildEnv, args: &[String]) -> Result<()> {
+        let tmp_dir = TempDir::new().with("/tmp")?;
+        for (compiler, src) in [(&self.cc, "hello.c"), (&self.cxx, "hello.cpp")] {
+            let path = tmp_dir.path().join(src);
+            let code = if src.ends_with(".c") {
+                HELLO_C
+            } else {
+                HELLO_CXX
+            };
+            fs::write(&path, code).with(&path)?;
+            Command::new(compiler)
+                .args(args)
+                .arg(&path)
+                .arg("-o")
+                .arg(tmp_dir.path().join("hello"))
+                .build_env(env)
+                .check_output()
+                .map_err(|e| {
+                    let message = match e {
+                        Error::CommandError {
+                            stderr: Some(stderr),
+                            ..
+                        } => format!("cannot compile a test program:\n{stderr}"),
+                        e => format!("cannot compile a test program: {e}"),
+                    };
+                    invalid(&compiler.display().to_string(), &message)
+                })?;
+        }
+        info!(
+            "Compiler works: {}, {}",
+            self.cc.display(),
+            self.cxx.display()
+        );
+        Ok(())
+    }
+
+    pub fn is_clang(&self) -> bool {
+        self.cc
+            .file_name()
+            .map(|name| name.to_string_lossy().contains("clang"))
+            .unwrap_or(false)
+    }
+
+    /// Options for cmake
+    pub fn options(&self) -> Vec<String> {
+        vec![
+            format!("-DCMAKE_C_COMPILER={}", self.cc.display()),
+            format!("-DCMAKE_CXX_COMPILER={}", self.cxx.display()),
+        ]
+    }
+}
+
+/// Compiler launcher, e.g. ccache
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Launcher {
+    #[default]
+    None,
+    Ccache,
+    Sccache,
+    Distcc,
+    Icecc,
+}
+
+impl Launcher {
+    fn command(&self) -> Option<&'static str> {
+        match self {
+            Launcher::None => None,
+            Launcher::Ccache => Some("ccache"),
+            Launcher::Sccache => Some("sccache"),
+            Launcher::Distcc => Some("distcc"),
+            Launcher::Icecc => Some("icecc"),
+        }
+    }
+
+    /// Check the launcher exists in `PATH` of the build, and returns options for cmake
+    pub fn options(&self, env: &BuildEnv) -> Result<Vec<String>> {
+        let cmd = match self.command() {
+            Some(cmd) => cmd,
+            None => return Ok(Vec::new()),
+        };
+        let path = env
+            .which(cmd)
+            .ok_or_else(|| Error::CommandNotFound { cmd: cmd.into() })?;
+        Ok(vec![
+            format!("-DCMAKE_C_COMPILER_LAUNCHER={}", path.display()),
+            format!("-DCMAKE_CXX_COMPILER_LAUNCHER={}", path.display()),
+        ])
+    }
+}
+
+/// Linker used to link LLVM/Clang
+///
+/// ```
+/// use llvmmgmt::toolchain::Linker;
+/// use std::str::FromStr;
+/// assert_eq!(Linker::from_str("lld").unwrap(), Linker::Lld);
+/// assert_eq!(Linker::from_str("default").unwrap(), Linker::Default);
+/// assert_eq!(Linker::from_str("/opt/bin/ld.lld").unwrap(), Linker::Path("/opt/bin/ld.lld".into()));
+/// assert!(Linker::from_str("bfd").is_err());
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+#[serde(try_from = "String", into = "String")]
+pub enum Linker {
+    /// Default linker of the compiler
+    #[default]
+    Default,
+    Lld,
+    Gold,
+    Mold,
+    /// Path to a linker
+    Path(PathBuf),
+}
+
+impl FromStr for Linker {
+    type Err = Error;
+    fn from_str(linker: &str) -> Result<Self> {
+        Ok(match linker {
+            "default" => Linker::Default,
+            "lld" => Linker::Lld,
+            "gold" => Linker::Gold,
+            "mold" => Linker::Mold,
+            path if path.contains('/') => Linker::Path(expand_path(path)),
+            _ => {
+                return Err(Error::UnsupportedLinker {
+                    linker: linker.into(),
+                })
+            }
+        })
+    }
+}
+
+impl TryFrom<String> for Linker {
+    type Error = Error;
+    fn try_from(linker: String) -> Result<Self> {
+        Linker::from_str(&linker)
+    }
+}
+
+impl From<Linker> for String {
+    fn from(linker: Linker) -> Self {
+        linker.to_string()
+    }
+}
+
+impl fmt::Display for Linker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Linker::Default => write!(f, "default"),
+            Linker::Lld => write!(f, "lld"),
+            Linker::Gold => write!(f, "gold"),
+            Linker::Mold => write!(f, "mold"),
+            Linker::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl Linker {
+    /// Check the linker exists in `PATH` of the build, and returns options for cmake
+    pub fn options(&self, env: &BuildEnv) -> Result<Vec<String>> {
+        let (cmd, value) = match self {
+            Linker::Default => return Ok(Vec::new()),
+            Linker::Lld => ("ld.lld", "lld".to_string()),
+            Linker::Gold => ("ld.gold", "gold".to_string()),
+            Linker::Mold => ("mold", "mold".to_string()),
+            Linker::Path(path) => {
+                if !path.is_file() {
+                    return Err(Error::CommandNotFound {
+                        cmd: path.display().to_string(),
+                    });
+                }
+                return Ok(vec![format!("-DLLVM_USE_LINKER={}", path.display())]);
+            }
+        };
+        env.which(cmd)
+            .ok_or_else(|| Error::CommandNotFound { cmd: cmd.into() })?;
+        Ok(vec![format!("-DLLVM_USE_LINKER={value}")])
+    }
+}
+
+/// Cross compile setting, `cross` table of an entry
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
+pub struct Cross {
+    /// Target triple of the host where the build runs, e.g. `aarch64-linux-gnu`
+    pub host_triple: String,
+
+    /// Sysroot of the host
+    pub sysroot: Option<String>,
+
+    /// C compiler producing binaries for the host. Default is clang with `--target={host_triple}`
+    pub compiler: Option<String>,
+
+    /// Directory containing `llvm-tblgen` (and `clang-tblgen`) runnable on the build machine
+    pub native_tool_dir: Option<String>,
+}
+
+impl Cross {
+    /// Generate CMake toolchain file and native TableGen, and returns options for cmake
+    pub fn prepare(&self, entry: &Entry) -> Result<Vec<String>> {
+        if let Some(compiler) = &entry.setting().compiler {
+            return Err(Error::InvalidEntry {
+                name: entry.name().into(),
+                message: format!(
+                    "compiler = \"{compiler}\" cannot be used with cross, use cross.compiler instead"
+                ),
+            });
+        }
+        let env = entry.environment()?;
+        self.check_compiler(&env)?;
+        let build_dir = entry.build_dir()?;
+        let toolchain = build_dir.join(format!("toolchain-{}.cmake", self.host_triple));
+        fs::write(&toolchain, self.toolchain_file(&env)?).with(&toolchain)?;
+        info!("Generate CMake toolchain file: {}", toolchain.display());
+
+        let mut opts = vec![
+            format!("-DCMAKE_TOOLCHAIN_FILE={}", toolchain.display()),
+            format!("-DLLVM_HOST_TRIPLE={}", self.host_triple),
+            format!("-DLLVM_DEFAULT_TARGET_TRIPLE={}", self.host_triple),
+        ];
+        let native_tool_dir = match &self.native_tool_dir {
+            Some(dir) => expand_path(dir),
+            None => build_native_tools(entry, &build_dir.join("native"))?,
+        };
+        let llvm_tblgen = native_tool_dir.join("llvm-tblgen");
+        if !llvm_tblgen.is_file() {
+            return Err(Error::InvalidEntry {
+                name: entry.name().into(),
+                message: format!("llvm-tblgen is not found in {}", native_tool_dir.display()),
+            });
+        }
+        opts.push(format!(
+            "-DLLVM_NATIVE_TOOL_DIR={}",
+            native_tool_dir.display()
+        ));
+        opts.push(format!("-DLLVM_TABLEGEN={}", llvm_tblgen.display()));
+        let clang_tblgen = native_tool_dir.join("clang-tblgen");
+        if clang_tblgen.exists() {
+            opts.push(format!("-DCLANG_TABLEGEN={}", clang_tblgen.display()));
+        }
+        Ok(opts)
+    }
+
+    /// C/C++ compilers producing binaries for the host
+    fn compiler(&self, env: &BuildEnv) -> Result<Compiler> {
+        Compiler::resolve(self.compiler.as_deref().unwrap_or("clang"), env)
+    }
+
+    /// Check that the compilers compile for the host, without linking which may need libraries of the host
+    fn check_compiler(&self, env: &BuildEnv) -> Result<()> {
+        let compiler = self.compiler(env)?;
+        let mut args = vec!["-c".to_string()];
+        if compiler.is_clang() {
+            args.push(format!("--target={}", self.host_triple));
+        }
+        if let Some(sysroot) = &self.sysroot {
+            args.push(format!("--sysroot={}", expand_path(sysroot).display()));
+        }
+        compiler.check_with(env, &args)
+    }
+
+    fn toolchain_file(&self, env: &BuildEnv) -> Result<String> {
+        let triple = &self.host_triple;
+        let processor = triple.split('-').next().unwrap_or(triple);
+        let system = if triple.contains("linux") {
+            "Linux"
+        } else if triple.contains("darwin") || triple.contains("apple") {
+            "Darwin"
+        } else if triple.contains("windows") || triple.contains("mingw") {
+            "Windows"
+        } else if triple.contains("freebsd") {
+            "FreeBSD"
+        } else {
+            "Generic"
+        };
+        let compiler = self.compiler(env)?;
+
+        let mut file = String::new();
+        file += &format!("set(CMAKE_SYSTEM_NAME {system})\n");
+        file += &format!("set(CMAKE_SYSTEM_PROCESSOR {processor})\n");
+        if let Some(sysroot) = &self.sysroot {
+            file += &format!(
+                "set(CMAKE_SYSROOT \"{}\")\n",
+                expand_path(sysroot).display()
+            );
+        }
+        file += &format!("set(CMAKE_C_COMPILER \"{}\")\n", compiler.cc.display());
+        file += &format!("set(CMAKE_CXX_COMPILER \"{}\")\n", compiler.cxx.display());
+        if compiler.is_clang() {
+            file += &format!("set(CMAKE_C_COMPILER_TARGET {triple})\n");
+            file += &format!("set(CMAKE_CXX_COMPILER_TARGET {triple})\n");
+        }
+        file += "set(CMAKE_FIND_ROOT_PATH_MODE_PROGRAM NEVER)\n";
+        file += "set(CMAKE_FIND_ROOT_PATH_MODE_LIBRARY ONLY)\n";
+        file += "set(CMAKE_FIND_ROOT_PATH_MODE_INCLUDE ONLY)\n";
+        file += "set(CMAKE_FIND_ROOT_PATH_MODE_PACKAGE ONLY)\n";
+        Ok(file)
+    }
+}
+
+/// Build TableGen for the build machine, and returns the directory containing them
+fn build_native_tools(entry: &Entry, native_dir: &Path) -> Result<PathBuf> {
+    let setting = entry.setting();
+    if !native_dir.exists() {
+        fs::create_dir_all(native_dir).with(native_dir)?;
+    }
+    info!("Build native TableGen in {}", native_dir.display());
+    let mut cmake = Command::new("cmake");
+    cmake
+        .args(entry.generator()?.option())
+        .arg(entry.src_dir()?)
+        .args(["-DCMAKE_BUILD_TYPE=Release", "-DLLVM_TARGETS_TO_BUILD=host"])
+        .current_dir(native_dir)
+        .build_env(&entry.environment()?);
+    if let Some(projects) = setting.option.get("LLVM_ENABLE_PROJECTS") {
+        cmake.arg(format!("-DLLVM_ENABLE_PROJECTS={}", projects.value()));
+    }
+    cmake.check_run()?;
+
+    let mut targets = vec!["llvm-tblgen"];
+    if native_dir.join("tools/clang").is_dir() {
+        targets.push("clang-tblgen");
+    }
+    for target in targets {
+        Command::new("cmake")
+            .arg("--build")
+            .arg(native_dir)
+            .args(["--target", target])
+            .build_env(&entry.environment()?)
+            .check_run()?;
+    }
+    Ok(native_dir.join("bin"))
+}
+
+/// Check if binaries for the target triple run on the current host
+///
+/// ```
+/// use llvmmgmt::toolchain::runs_on_host;
+/// assert!(!runs_on_host("no-such-arch-unknown-os"));
+/// ```
+pub fn runs_on_host(triple: &str) -> bool {
+    let mut arch = triple.split('-').next().unwrap_or(triple);
+    arch = match arch {
+        "amd64" => "x86_64",
+        "arm64" => "aarch64",
+        "i386" | "i486" | "i586" | "i686" => "x86",
+        arch if arch.starts_with("armv") => "arm",
+        arch => arch,
+    };
+    let os = match env::consts::OS {
+        "macos" => "darwin",
+        os => os,
+    };
+    arch == env::consts::ARCH && triple.contains(os)
+}
+
+fn invalid(compiler: &str, message: &str) -> Error {
+    Error::InvalidCompiler {
+        compiler: compiler.into(),
+        message: message.into(),
+    }
+}
+
+/// Guess C++ compiler name from C compiler name
+fn cxx_name(cc: &str) -> Option<String> {
+    if let Some(pos) = cc.rfind("clang") {
+        return Some(format!("{}clang++{}", &cc[..pos], &cc[pos + 5..]));
+    }
+    if let Some(pos) = cc.rfind("gcc") {
+        return Some(format!("{}g++{}", &cc[..pos], &cc[pos + 3..]));
+    }
+    // Classic Intel compiler
+    if let Some(pos) = cc.rfind("icc") {
+        return Some(format!("{}icpc{}", &cc[..pos], &cc[pos + 3..]));
+    }
+    if let Some(pos) = cc.rfind("cc") {
+        return Some(format!("{}c++{}", &cc[..pos], &cc[pos + 2..]));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::EntrySetting;
+
+    #[test]
+    fn guess_cxx_name() {
+        assert_eq!(cxx_name("gcc").unwrap(), "g++");
+        assert_eq!(cxx_name("gcc-11").unwrap(), "g++-11");
+        assert_eq!(cxx_name("clang-15").unwrap(), "clang++-15");
+        assert_eq!(
+            cxx_name("x86_64-linux-gnu-gcc-11").unwrap(),
+            "x86_64-linux-gnu-g++-11"
+        );
+        assert_eq!(cxx_name("cc").unwrap(), "c++");
+        assert_eq!(cxx_name("icc").unwrap(), "icpc");
+        assert_eq!(cxx_name("icx"), None);
+    }
+
+    #[test]
+    fn resolve_missing_build() {
+        assert!(Compiler::resolve("build:no-such-build", &BuildEnv::default()).is_err());
+    }
+
+    #[test]
+    fn host_triple() {
+        let os = if cfg!(target_os = "macos") {
+            "darwin"
+        } else {
+            env::consts::OS
+        };
+        assert!(runs_on_host(&format!(
+            "{}-unknown-{}",
+            env::consts::ARCH,
+            os
+        )));
+        assert!(!runs_on_host(&format!(
+            "{}-unknown-no-such-os",
+            env::consts::ARCH
+        )));
+    }
+
+    /// Empty files standing for the C and C++ compilers
+    /// Compilers which succeed without output, or fail if `works` is false
+    fn fake_compilers(dir: &Path, cc: &str, works: bool) -> Result<PathBuf> {
+        let script = if works {
+            "#!/bin/sh\n"
+        } else {
+            "#!/bin/sh\nexit 1\n"
+        };
+        for name in [cc.to_string(), cxx_name(cc).unwrap()] {
+            let path = dir.join(name);
+            fs::write(&path, script).with(&path)?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).with(&path)?;
+            }
+        }
+        Ok(dir.join(cc))
+    }
+
+    #[test]
+    fn toolchain_file() -> Result<()> {
+        let bin = TempDir::new().with("/tmp")?;
+        let gcc = fake_compilers(bin.path(), "aarch64-linux-gnu-gcc", true)?;
+        let cross = Cross {
+            host_triple: "aarch64-linux-gnu".into(),
+            sysroot: Some("/usr/aarch64-linux-gnu".into()),
+            compiler: Some(gcc.display().to_string()),
+            native_tool_dir: None,
+        };
+        assert_eq!(
+            cross.toolchain_file(&BuildEnv::default())?,
+            format!(
+                "set(CMAKE_SYSTEM_NAME Linux)\n\
+                 set(CMAKE_SYSTEM_PROCESSOR aarch64)\n\
+                 set(CMAKE_SYSROOT \"/usr/aarch64-linux-gnu\")\n\
+                 set(CMAKE_C_COMPILER \"{}\")\n\
+                 set(CMAKE_CXX_COMPILER \"{}\")\n\
+                 set(CMAKE_FIND_ROOT_PATH_MODE_PROGRAM NEVER)\n\
+                 set(CMAKE_FIND_ROOT_PATH_MODE_LIBRARY ONLY)\n\
+                 set(CMAKE_FIND_ROOT_PATH_MODE_INCLUDE ONLY)\n\
+                 set(CMAKE_FIND_ROOT_PATH_MODE_PACKAGE ONLY)\n",
+                gcc.display(),
+                bin.path().join("aarch64-linux-gnu-g++").display()
+            )
+        );
+
+        // clang is a cross compiler by --target
+        let clang = fake_compilers(bin.path(), "clang", true)?;
+        let cross = Cross {
+            host_triple: "riscv64-unknown-freebsd".into(),
+            compiler: Some(clang.display().to_string()),
+            ..Default::default()
+        };
+        let file = cross.toolchain_file(&BuildEnv::default())?;
+        assert!(file
+            .starts_with("set(CMAKE_SYSTEM_NAME FreeBSD)\nset(CMAKE_SYSTEM_PROCESSOR riscv64)\n"));
+        assert!(!file.contains("CMAKE_SYSROOT"));
+        assert!(file.contains("set(CMAKE_C_COMPILER_TARGET riscv64-unknown-freebsd)\n"));
+        assert!(file.contains("set(CMAKE_CXX_COMPILER_TARGET riscv64-unknown-freebsd)\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn prepare_cross() -> Result<()> {
+        let src = TempDir::new().with("/tmp")?;
+        let native = TempDir::new().with("/tmp")?;
+        let gcc = fake_compilers(native.path(), "aarch64-linux-gnu-gcc", true)?;
+        fs::write(native.path().join("llvm-tblgen"), "").with(native.path())?;
+        fs::write(native.path().join("clang-tblgen"), "").with(native.path())?;
+        let cross = Cross {
+            host_triple: "aarch64-linux-gnu".into(),
+            compiler: Some(gcc.display().to_string()),
+            native_tool_dir: Some(native.path().display().to_string()),
+            ..Default::default()
+        };
+        let mut setting = EntrySetting {
+            path: Some(src.path().display().to_string()),
+            cross: Some(cross.clone()),
+            ..Default::default()
+        };
+        let entry = Entry::parse_setting("cross", None, setting.clone())?;
+        let toolchain = entry.build_dir()?.join("toolchain-aarch64-linux-gnu.cmake");
+        assert_eq!(
+            cross.prepare(&entry)?,
+            [
+                format!("-DCMAKE_TOOLCHAIN_FILE={}", toolchain.display()),
+                "-DLLVM_HOST_TRIPLE=aarch64-linux-gnu".into(),
+                "-DLLVM_DEFAULT_TARGET_TRIPLE=aarch64-linux-gnu".into(),
+                format!("-DLLVM_NATIVE_TOOL_DIR={}", native.path().display()),
+                format!(
+                    "-DLLVM_TABLEGEN={}",
+                    native.path().join("llvm-tblgen").display()
+                ),
+                format!(
+                    "-DCLANG_TABLEGEN={}",
+                    native.path().join("clang-tblgen").display()
+                ),
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(&toolchain).with(&toolchain)?,
+            cross.toolchain_file(&BuildEnv::default())?
+        );
+
+        // llvm-tblgen is required
+        fs::remove_file(native.path().join("llvm-tblgen")).with(native.path())?;
+        assert!(matches!(
+            cross.prepare(&entry),
+            Err(Error::InvalidEntry { .. })
+        ));
+
+        // The cross compiler is checked
+        let broken = TempDir::new().with("/tmp")?;
+        let broken_gcc = fake_compilers(broken.path(), "aarch64-linux-gnu-gcc", false)?;
+        let broken_cross = Cross {
+            compiler: Some(broken_gcc.display().to_string()),
+            ..cross.clone()
+        };
+        assert!(matches!(
+            broken_cross.prepare(&entry),
+            Err(Error::InvalidCompiler { .. })
+        ));
+
+        // The host compiler is set by cross.compiler
+        setting.compiler = Some("gcc".into());
+        let entry = Entry::parse_setting("cross", None, setting)?;
+        assert!(matches!(
+            cross.prepare(&entry),
+            Err(Error::InvalidEntry { .. })
+        ));
+        Ok(())
+    }
+
+    /// Requires cmake and a checkout of LLVM, which is replaced by a project
+    /// with `llvm-tblgen` target writing a script
+    #[ignore]
+    #[test]
+    fn native_tools() -> Result<()> {
+        let src = TempDir::new().with("/tmp")?;
+        let cmake_lists = src.path().join("CMakeLists.txt");
+        fs::write(
+            &cmake_lists,
+            "cmake_minimum_required(VERSION 3.13)\n\
+             project(fake-llvm NONE)\n\
+             add_custom_target(llvm-tblgen\n  \
+             COMMAND ${CMAKE_COMMAND} -E make_directory bin\n  \
+             COMMAND ${CMAKE_COMMAND} -E touch bin/llvm-tblgen)\n",
+        )
+        .with(&cmake_lists)?;
+        let setting = EntrySetting {
+            path: Some(src.path().display().to_string()),
+            ..Default::default()
+        };
+        let entry = Entry::parse_setting("native", None, setting)?;
+        let native_dir = src.path().join("native");
+        let bin = build_native_tools(&entry, &native_dir)?;
+        assert_eq!(bin, native_dir.join("bin"));
+        assert!(bin.join("llvm-tblgen").exists());
+        assert!(!bin.join("clang-tblgen").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+//! Build variants, i.e. [profiles](../profile/index.html) installed side by side with the plain build
+//!
+//! A variant is installed as `{entry}-{variant}`,
+//! e.g. `llvmmgmt install 18.1.8 --variant asserts` installs a build named `18.1.8-asserts`.
+//!
+//! Built-in variants are
+//!
+//! - `debug`: `CMAKE_BUILD_TYPE=Debug`
+//! - `relwithdebinfo`: `CMAKE_BUILD_TYPE=RelWithDebInfo`
+//! - `asserts`: `LLVM_ENABLE_ASSERTIONS=ON`
+//!
+//! and you can define your own variants in the `[variant.<name>]` table of `$XDG_CONFIG_HOME/llvmmgmt/config.toml`
+//! with the same fields as profiles, which take precedence over the built-in ones.
+
+use crate::{
+    config::*,
+    entry::BuildType,
+    error::*,
+    profile::{self, Profile},
+};
+
+/// Variants shipped with llvmmgmt
+pub fn builtin_variants() -> Vec<(&'static str, Profile)> {
+    let build_type = |build_type| Profile {
+        build_type: Some(build_type),
+        ..Default::default()
+    };
+    vec![
+        ("debug", build_type(BuildType::Debug)),
+        ("relwithdebinfo", build_type(BuildType::RelWithDebInfo)),
+        (
+            "asserts",
+            Profile::with_options(&[("LLVM_ENABLE_ASSERTIONS", true)]),
+        ),
+    ]
+}
+
+/// Find a variant from config.toml, and then from the built-in variants
+pub fn load_variant(name: &str) -> Result<Profile> {
+    profile::find(name, &Config::global()?.variant, builtin_variants()).ok_or_else(|| {
+        Error::InvalidVariant {
+            name: name.into(),
+            message: "Variant not found".into(),
+        }
+    })
+}
+
+/// Name of the build of an entry with a variant
+///
+/// ```
+/// use llvmmgmt::variant::build_name;
+/// assert_eq!(build_name("18.1.8", Some("asserts")), "18.1.8-asserts");
+/// assert_eq!(build_name("18.1.8", None), "18.1.8");
+/// ```
+pub fn build_name(entry: &str, variant: Option<&str>) -> String {
+    match variant {
+        Some(variant) => format!("{entry}-{variant}"),
+        None => entry.into(),
+    }
+}
@@ -0,0 +1,454 @@
+//! Verification of an installed build
+//!
+//! A prefix with `bin` is listed as a build even if the install is broken.
+//! `llvmmgmt verify <build>` checks that the build actually works:
+//!
+//! | Check              | Description                                                       |
+//! |:-------------------|:------------------------------------------------------------------|
+//! | `llvm-config`      | `llvm-config --version` matches the version of the entry          |
+//! | `compile-c`        | Hello world in C is compiled, linked, and run by the build's clang |
+//! | `compile-cxx`      | Same in C++ by clang++                                            |
+//! | `shared-libraries` | Shared libraries of the installed binaries are resolved (Linux)  |
+//! | `tools`            | Tools which the entry should provide are installed                |
+//!
+//! Each check is `ok`, `failed`, or `skipped`, e.g. compiling is skipped for a build without clang,
+//! and the command exits with an error if any check fails. `--json` prints the report for CI:
+//!
+//! ```json
+//! {
+//!   "build": "18.1.8",
+//!   "ok": false,
+//!   "checks": [
+//!     { "name": "llvm-config", "status": "ok", "message": "18.1.8" },
+//!     { "name": "tools", "status": "failed", "message": "Missing tools: ld.lld" }
+//!   ]
+//! }
+//! ```
+
+use log::warn;
+use semver::Version;
+use serde_derive::Serialize;
+use std::{fmt, fs, path::Path, process::Command};
+
+use crate::{
+    build::{Build, BuildInfo},
+    entry::Entry,
+    error::*,
+    install,
+};
+
+/// Result of a check
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Failed,
+    Skipped,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub message: String,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Status, message: impl Into<String>) -> Self {
+        Check {
+            name,
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+/// Verification report of a build
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Report {
+    pub build: String,
+    /// No check failed
+    pub ok: bool,
+    pub checks: Vec<Check>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for check in &self.checks {
+            let status = match check.status {
+                Status::Ok => "ok",
+                Status::Failed => "FAILED",
+                Status::Skipped => "skipped",
+            };
+            writeln!(f, "{:>16}: {:<7} {}", check.name, status, check.message)?;
+        }
+        Ok(())
+    }
+}
+
+/// Run all checks on a build
+pub fn verify(build: &Build) -> Result<Report> {
+    let prefix = build.prefix();
+    let info = build.info()?;
+    let entry = info.as_ref().and_then(|info| match info.load_entry() {
+        Ok(entry) => Some(entry),
+        Err(e) => {
+            warn!("Cannot load entry {} of the build: {}", info.entry, e);
+            None
+        }
+    });
+    let runnable = info.as_ref().is_none_or(|info| info.is_runnable());
+    let expected = expected_tools(info.as_ref(), entry.as_ref());
+    let expects = |tool: &str| {
+        expected
+            .as_ref()
+            .is_some_and(|tools| tools.iter().any(|t| t == tool))
+    };
+
+    let checks = if runnable {
+        vec![
+            check_version(
+                prefix,
+                entry.as_ref().and_then(|e| e.version()),
+                expects("llvm-config"),
+            ),
+            check_compile(prefix, Lang::C, expects("clang"))?,
+            check_compile(prefix, Lang::Cxx, expects("clang++"))?,
+            if build.name() == "system" {
+                Check::new(
+                    "shared-libraries",
+                    Status::Skipped,
+                    "Not installed by llvmmgmt",
+                )
+            } else {
+                check_shared_libraries(prefix)?
+            },
+            check_tools(prefix, expected.as_deref()),
+        ]
+    } else {
+        let message = "Cross compiled build does not run on this host";
+        vec![
+            Check::new("llvm-config", Status::Skipped, message),
+            Check::new("compile-c", Status::Skipped, message),
+            Check::new("compile-cxx", Status::Skipped, message),
+            Check::new("shared-libraries", Status::Skipped, message),
+            check_tools(prefix, expected.as_deref()),
+        ]
+    };
+    Ok(Report {
+        build: build.name().into(),
+        ok: checks.iter().all(|check| check.status != Status::Failed),
+        checks,
+    })
+}
+
+/// Tools which should be in `{prefix}/bin`, `None` if unknown
+fn expected_tools(info: Option<&BuildInfo>, entry: Option<&Entry>) -> Option<Vec<String>> {
+    let info = info?;
+    if info.is_partial() {
+        return Some(tool_names(&info.targets));
+    }
+    if !info.components.is_empty() {
+        return Some(tool_names(&info.components));
+    }
+    let setting = entry?.setting();
+    let mut projects: Vec<String> = setting.tools.iter().map(|tool| tool.name.clone()).collect();
+    if let Some(enabled) = setting.option.get("LLVM_ENABLE_PROJECTS") {
+        projects.extend(enabled.value().split(';').map(str::to_string));
+    }
+    let mut tools: Vec<String> = ["llvm-config", "llvm-ar", "opt", "llc"]
+        .iter()
+        .map(|tool| tool.to_string())
+        .collect();
+    for project in &projects {
+        let provided: &[&str] = match project.as_str() {
+            "clang" => &["clang", "clang++"],
+            "lld" => &["ld.lld"],
+            "lldb" => &["lldb"],
+            "clang-tools-extra" | "clang-extra" => &["clang-tidy", "clangd"],
+            _ => &[],
+        };
+        for tool in provided {
+            if !tools.iter().any(|t| t == tool) {
+                tools.push(tool.to_string());
+            }
+        }
+    }
+    Some(tools)
+}
+
+/// Names of targets or distribution components installed as executables,
+/// i.e. except libraries like `LLVMSupport` and `LTO` and files like `clang-resource-headers`
+fn tool_names(targets: &[String]) -> Vec<String> {
+    targets
+        .iter()
+        .filter(|name| {
+            !(name.starts_with(|c: char| c.is_ascii_uppercase())
+                || name.starts_with("lib")
+                || name.ends_with("-headers")
+                || name.ends_with("-exports")
+                || [
+                    "builtins",
+                    "runtimes",
+                    "compiler-rt",
+                    "cxx",
+                    "cxxabi",
+                    "unwind",
+                ]
+                .contains(&name.as_str()))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Version printed by llvm-config without suffix like `git`
+fn parse_llvm_config_version(output: &str) -> Option<Version> {
+    let version = output.trim();
+    let end = version
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(version.len());
+    Version::parse(&version[..end]).ok()
+}
+
+fn check_version(prefix: &Path, expected: Option<&Version>, required: bool) -> Check {
+    const NAME: &str = "llvm-config";
+    let llvm_config = prefix.join("bin/llvm-config");
+    if !llvm_config.exists() {
+        return if required {
+            Check::new(NAME, Status::Failed, "llvm-config is not installed")
+        } else {
+            Check::new(NAME, Status::Skipped, "llvm-config is not installed")
+        };
+    }
+    let output = match Command::new(&llvm_config).arg("--version").check_output() {
+        Ok((stdout, _)) => stdout,
+        Err(e) => return Check::new(NAME, Status::Failed, e.to_string()),
+    };
+    let version = output.trim();
+    match (expected, parse_llvm_config_version(version)) {
+        (Some(expected), Some(actual)) if &actual != expected => Check::new(
+            NAME,
+            Status::Failed,
+            format!("{} is expected, but got {}", expected, version),
+        ),
+        (Some(_), None) => Check::new(
+            NAME,
+            Status::Failed,
+            format!("Invalid version: {}", version),
+        ),
+        _ => Check::new(NAME, Status::Ok, version),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Lang {
+    C,
+    Cxx,
+}
+
+fn check_compile(prefix: &Path, lang: Lang, required: bool) -> Result<Check> {
+    let (name, compiler, source, code) = match lang {
+        Lang::C => (
+            "compile-c",
+            "clang",
+            "hello.c",
+            "#include <stdio.h>\nint main(void) { puts(\"hello\"); return 0; }\n",
+        ),
+        Lang::Cxx => (
+            "compile-cxx",
+            "clang++",
+            "hello.cpp",
+            "#include <iostream>\nint main() { std::cout << \"hello\" << std::endl; }\n",
+        ),
+    };
+    let compiler = prefix.join("bin").join(compiler);
+    if !compiler.exists() {
+        let status = if required {
+            Status::Failed
+        } else {
+            Status::Skipped
+        };
+        return Ok(Check::new(
+            name,
+            status,
+            format!("{} is not installed", compiler.display()),
+        ));
+    }
+    let dir = tempfile::TempDir::new().with("/tmp")?;
+    let source = dir.path().join(source);
+    fs::write(&source, code).with(&source)?;
+    let exe = dir.path().join("hello");
+    let result = Command::new(&compiler)
+        .arg(&source)
+        .arg("-o")
+        .arg(&exe)
+        .check_output()
+        .and_then(|_| Command::new(&exe).check_output());
+    Ok(match result {
+        Ok((stdout, _)) if stdout.trim() == "hello" => Check::new(name, Status::Ok, ""),
+        Ok((stdout, _)) => Check::new(
+            name,
+            Status::Failed,
+            format!("Unexpected output: {}", stdout.trim()),
+        ),
+        Err(Error::CommandError {
+            stderr: Some(stderr),
+            ..
+        }) => Check::new(name, Status::Failed, stderr.trim()),
+        Err(e) => Check::new(name, Status::Failed, e.to_string()),
+    })
+}
+
+/// Libraries reported as `libfoo.so => not found` by ldd
+fn unresolved_libraries(ldd_output: &str) -> Vec<String> {
+    ldd_output
+        .lines()
+        .filter(|line| line.contains("=> not found"))
+        .filter_map(|line| line.split_whitespace().next())
+        .map(str::to_string)
+        .collect()
+}
+
+fn check_shared_libraries(prefix: &Path) -> Result<Check> {
+    const NAME: &str = "shared-libraries";
+    if !cfg!(target_os = "linux") {
+        return Ok(Check::new(NAME, Status::Skipped, "Supported only on Linux"));
+    }
+    let binaries = install::installed_binaries(prefix)?;
+    let mut broken = Vec::new();
+    for binary in &binaries {
+        // ldd fails for static executables
+        if let Ok((stdout, _)) = Command::new("ldd").arg(binary).check_output() {
+            let mut missing = unresolved_libraries(&stdout);
+            missing.sort();
+            missing.dedup();
+            if !missing.is_empty() {
+                let rel = binary.strip_prefix(prefix).unwrap_or(binary);
+                broken.push(format!("{} ({})", rel.display(), missing.join(", ")));
+            }
+        }
+    }
+    Ok(if broken.is_empty() {
+        Check::new(
+            NAME,
+            Status::Ok,
+            format!("{} binaries checked", binaries.len()),
+        )
+    } else {
+        Check::new(
+            NAME,
+            Status::Failed,
+            format!("Unresolved libraries: {}", broken.join("; ")),
+        )
+    })
+}
+
+fn check_tools(prefix: &Path, expected: Option<&[String]>) -> Check {
+    const NAME: &str = "tools";
+    let expected = match expected {
+        Some(expected) => expected,
+        None => return Check::new(NAME, Status::Skipped, "Entry of the build is unknown"),
+    };
+    let missing: Vec<&str> = expected
+        .iter()
+        .filter(|tool| !prefix.join("bin").join(tool).exists())
+        .map(String::as_str)
+        .collect();
+    if missing.is_empty() {
+        Check::new(NAME, Status::Ok, expected.join(", "))
+    } else {
+        Check::new(
+            NAME,
+            Status::Failed,
+            format!("Missing tools: {}", missing.join(", ")),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expected_tools_of_entry() {
+        let info = BuildInfo {
+            entry: "18.1.8".into(),
+            ..Default::default()
+        };
+        let entry = Entry::official(18, 1, 8);
+        let tools = expected_tools(Some(&info), Some(&entry)).unwrap();
+        for tool in ["llvm-config", "clang++", "ld.lld", "clangd"] {
+            assert!(tools.iter().any(|t| t == tool), "{tool} is expected");
+        }
+        assert_eq!(expected_tools(None, Some(&entry)), None);
+
+        let partial = BuildInfo {
+            targets: vec!["clang-format".into(), "LLVMSupport".into()],
+            ..info.clone()
+        };
+        assert_eq!(
+            expected_tools(Some(&partial), Some(&entry)).unwrap(),
+            ["clang-format"]
+        );
+        let components = BuildInfo {
+            components: vec![
+                "clang".into(),
+                "clang-resource-headers".into(),
+                "LTO".into(),
+            ],
+            ..info
+        };
+        assert_eq!(expected_tools(Some(&components), None).unwrap(), ["clang"]);
+    }
+
+    #[test]
+    fn llvm_config_version() {
+        assert_eq!(
+            parse_llvm_config_version("18.1.8\n"),
+            Some(Version::new(18, 1, 8))
+        );
+        assert_eq!(
+            parse_llvm_config_version("19.0.0git\n"),
+            Some(Version::new(19, 0, 0))
+        );
+        assert_eq!(parse_llvm_config_version("unknown"), None);
+    }
+
+    #[test]
+    fn ldd_output() {
+        let output = "\tlinux-vdso.so.1 (0x00007ffd)\n\
+                      \tlibLLVM.so.18.1 => not found\n\
+                      \tlibc.so.6 => /lib/x86_64-linux-gnu/libc.so.6 (0x00007f)\n";
+        assert_eq!(unresolved_libraries(output), ["libLLVM.so.18.1"]);
+    }
+
+    #[test]
+    fn verify_broken_prefix() -> Result<()> {
+        let prefix = tempfile::TempDir::new().with("/tmp")?;
+        let bin = prefix.path().join("bin");
+        fs::create_dir_all(&bin).with(&bin)?;
+        let llvm_config = bin.join("llvm-config");
+        fs::write(&llvm_config, "#!/bin/sh\necho 18.1.7\n").with(&llvm_config)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&llvm_config, fs::Permissions::from_mode(0o755))
+                .with(&llvm_config)?;
+        }
+        let check = check_version(prefix.path(), Some(&Version::new(18, 1, 8)), true);
+        assert_eq!(check.status, Status::Failed);
+        assert_eq!(check.message, "18.1.8 is expected, but got 18.1.7");
+
+        let check = check_compile(prefix.path(), Lang::C, false)?;
+        assert_eq!(check.status, Status::Skipped);
+
+        let tools = vec!["llvm-config".to_string(), "clang".to_string()];
+        let check = check_tools(prefix.path(), Some(&tools));
+        assert_eq!(check.message, "Missing tools: clang");
+        assert_eq!(
+            serde_json::to_value(&check)?,
+            serde_json::json!({ "name": "tools", "status": "failed", "message": "Missing tools: clang" })
+        );
+        Ok(())
+    }
+}